@@ -0,0 +1,177 @@
+//! WinZip AES encryption of entry data, AES-256 in CTR mode authenticated
+//! with HMAC-SHA1, keys derived from the password with PBKDF2.
+
+use aes::{
+    cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit},
+    Aes256,
+};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+use crate::async_zip::error::{Error, Result};
+
+pub const SALT_LEN: usize = 16;
+pub const VERIFIER_LEN: usize = 2;
+pub const AUTH_CODE_LEN: usize = 10;
+/// Bytes added to the compressed data: salt, password verifier and
+/// authentication code
+pub const OVERHEAD: u64 = (SALT_LEN + VERIFIER_LEN + AUTH_CODE_LEN) as u64;
+
+const KEY_LEN: usize = 32;
+const ITERATIONS: u32 = 1000;
+const BLOCK_LEN: usize = 16;
+
+type HmacSha1 = Hmac<Sha1>;
+
+/// Encryption key, authentication key and password verifier.
+fn derive_keys(password: &str, salt: &[u8]) -> (Aes256, HmacSha1, [u8; VERIFIER_LEN]) {
+    let mut keys = [0; 2 * KEY_LEN + VERIFIER_LEN];
+    pbkdf2::pbkdf2::<HmacSha1>(password.as_bytes(), salt, ITERATIONS, &mut keys);
+
+    let cipher = Aes256::new(GenericArray::from_slice(&keys[..KEY_LEN]));
+    let mac =
+        <HmacSha1 as Mac>::new_from_slice(&keys[KEY_LEN..2 * KEY_LEN]).expect("any key length");
+    let mut verifier = [0; VERIFIER_LEN];
+    verifier.copy_from_slice(&keys[2 * KEY_LEN..]);
+    (cipher, mac, verifier)
+}
+
+/// AES in CTR mode with a little-endian counter starting at 1, as WinZip
+/// does it.
+struct Ctr {
+    cipher: Aes256,
+    counter: u128,
+    keystream: [u8; BLOCK_LEN],
+    used: usize,
+}
+
+impl Ctr {
+    fn new(cipher: Aes256) -> Self {
+        Ctr {
+            cipher,
+            counter: 0,
+            keystream: [0; BLOCK_LEN],
+            used: BLOCK_LEN,
+        }
+    }
+
+    fn apply(&mut self, data: &mut [u8]) {
+        for byte in data {
+            if self.used == BLOCK_LEN {
+                self.counter = self.counter.wrapping_add(1);
+                let mut block = GenericArray::from(self.counter.to_le_bytes());
+                self.cipher.encrypt_block(&mut block);
+                self.keystream.copy_from_slice(&block);
+                self.used = 0;
+            }
+            *byte ^= self.keystream[self.used];
+            self.used += 1;
+        }
+    }
+}
+
+/// Encrypts the data of one entry, fed chunk by chunk.
+pub struct Encryptor {
+    ctr: Ctr,
+    mac: HmacSha1,
+}
+
+impl Encryptor {
+    /// Starts an entry with a random salt, the returned salt and password
+    /// verifier go before the encrypted data.
+    pub fn new(password: &str) -> Result<(Self, Vec<u8>)> {
+        let mut salt = [0; SALT_LEN];
+        getrandom::getrandom(&mut salt).map_err(|e| Error::Io(e.into()))?;
+        Ok(Encryptor::with_salt(password, salt))
+    }
+
+    fn with_salt(password: &str, salt: [u8; SALT_LEN]) -> (Self, Vec<u8>) {
+        let (cipher, mac, verifier) = derive_keys(password, &salt);
+        let mut header = salt.to_vec();
+        header.extend_from_slice(&verifier);
+        (
+            Encryptor {
+                ctr: Ctr::new(cipher),
+                mac,
+            },
+            header,
+        )
+    }
+
+    pub fn encrypt(&mut self, mut data: Vec<u8>) -> Vec<u8> {
+        self.ctr.apply(&mut data);
+        self.mac.update(&data);
+        data
+    }
+
+    /// Authentication code of the encrypted data, it goes after it.
+    pub fn finish(self) -> Vec<u8> {
+        self.mac.finalize().into_bytes()[..AUTH_CODE_LEN].to_vec()
+    }
+}
+
+/// Decrypts the data of one entry, fed chunk by chunk.
+pub struct Decryptor {
+    ctr: Ctr,
+    mac: HmacSha1,
+}
+
+impl Decryptor {
+    /// `header` is the salt and password verifier before the data of the
+    /// entry `name`.
+    pub fn new(password: &str, header: &[u8], name: &str) -> Result<Self> {
+        let (salt, verifier) = header.split_at(SALT_LEN);
+        let (cipher, mac, expected) = derive_keys(password, salt);
+        if verifier != expected {
+            return Err(Error::WrongPassword(name.to_owned()));
+        }
+        Ok(Decryptor {
+            ctr: Ctr::new(cipher),
+            mac,
+        })
+    }
+
+    pub fn decrypt(&mut self, mut data: Vec<u8>) -> Vec<u8> {
+        self.mac.update(&data);
+        self.ctr.apply(&mut data);
+        data
+    }
+
+    /// Whether `auth_code` authenticates the data decrypted so far.
+    pub fn verify(self, auth_code: &[u8]) -> bool {
+        self.mac.verify_truncated_left(auth_code).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let (mut encryptor, header) = Encryptor::with_salt("secret", [7; SALT_LEN]);
+        assert_eq!(header.len(), SALT_LEN + VERIFIER_LEN);
+        let content = b"customer data, customer data, customer data".to_vec();
+        let mut encrypted = encryptor.encrypt(content[..5].to_vec());
+        encrypted.extend(encryptor.encrypt(content[5..].to_vec()));
+        assert_ne!(encrypted, content);
+        let auth_code = encryptor.finish();
+
+        let mut decryptor = Decryptor::new("secret", &header, "data")?;
+        let decrypted = decryptor.decrypt(encrypted.clone());
+        assert_eq!(decrypted, content);
+        assert!(decryptor.verify(&auth_code));
+
+        // tampered data fails the authentication
+        let mut decryptor = Decryptor::new("secret", &header, "data")?;
+        encrypted[0] ^= 1;
+        decryptor.decrypt(encrypted);
+        assert!(!decryptor.verify(&auth_code));
+
+        assert!(matches!(
+            Decryptor::new("guess", &header, "data"),
+            Err(Error::WrongPassword(_))
+        ));
+        Ok(())
+    }
+}
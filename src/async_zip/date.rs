@@ -1,36 +1,95 @@
-use crate::async_zip::error::{Error, Result};
-use chrono::{DateTime, Datelike, Local, Timelike};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, Timelike, Utc};
 use std::time::SystemTime;
 
-pub struct Timestamp(DateTime<Local>);
+/// Modification and access times of an entry.
+///
+/// The DOS date and time fields hold the wall clock modification time,
+/// clamped to the DOS range 1980 - 2107, the extended timestamp extra field
+/// holds both times in seconds since the Unix epoch.
+#[derive(Debug, Clone, Copy)]
+pub struct Timestamp {
+    dos: NaiveDateTime,
+    mtime: i32,
+    atime: i32,
+}
 
 impl Timestamp {
-    // pub fn local(&self) -> DateTime<Local> {
-    //     self.0.into()
-    // }
+    /// `modified` in the local time zone.
+    pub fn local(modified: SystemTime) -> Self {
+        Timestamp::new(modified, DateTime::<Local>::from(modified).naive_local())
+    }
+
+    /// `modified` in UTC, independent of the host time zone.
+    pub fn utc(modified: SystemTime) -> Self {
+        Timestamp::new(modified, DateTime::<Utc>::from(modified).naive_utc())
+    }
+
+    fn new(modified: SystemTime, wall_clock: NaiveDateTime) -> Self {
+        let mtime = unix_time(modified);
+        Timestamp {
+            dos: wall_clock.clamp(dos_min(), dos_max()),
+            mtime,
+            atime: mtime,
+        }
+    }
+
+    /// Sets the access time, it defaults to the modification time.
+    pub fn accessed(mut self, accessed: SystemTime) -> Self {
+        self.atime = unix_time(accessed);
+        self
+    }
 
     pub fn dos_timepart(&self) -> u16 {
-        let t = self.0.time();
+        let t = self.dos.time();
         ((t.second() as u16) >> 1) | ((t.minute() as u16) << 5) | ((t.hour() as u16) << 11)
     }
 
-    pub fn dos_datepart(&self) -> Result<u16> {
-        let d = self.0.date();
-        if d.year() < 1980 || d.year() > 2107 {
-            return Err(Error::InvalidYear(d.year()));
-        }
-        Ok((d.day() as u16) | ((d.month() as u16) << 5) | (((d.year() - 1980) as u16) << 9))
+    pub fn dos_datepart(&self) -> u16 {
+        let d = self.dos.date();
+        (d.day() as u16) | ((d.month() as u16) << 5) | (((d.year() - 1980) as u16) << 9)
+    }
+
+    /// Modification time in seconds since the Unix epoch.
+    pub fn mtime(&self) -> i32 {
+        self.mtime
+    }
+
+    /// Access time in seconds since the Unix epoch.
+    pub fn atime(&self) -> i32 {
+        self.atime
     }
 }
 
 impl From<SystemTime> for Timestamp {
     fn from(t: SystemTime) -> Self {
-        Timestamp(t.into())
+        Timestamp::local(t)
     }
 }
 
+/// Seconds since the Unix epoch, clamped to the signed 32-bit range of the
+/// extended timestamp.
+fn unix_time(t: SystemTime) -> i32 {
+    DateTime::<Utc>::from(t)
+        .timestamp()
+        .clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+fn dos_min() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(1980, 1, 1)
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .expect("valid date")
+}
+
+fn dos_max() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2107, 12, 31)
+        .and_then(|d| d.and_hms_opt(23, 59, 58))
+        .expect("valid date")
+}
+
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use chrono::Datelike;
 
     use super::*;
@@ -42,4 +101,28 @@ mod tests {
 
         assert_eq!(dt.year(), dt2.year());
     }
+
+    #[test]
+    fn test_utc_dos_parts() {
+        // 1980-01-01 00:00:00 UTC
+        let t = Timestamp::utc(SystemTime::UNIX_EPOCH + Duration::from_secs(315_532_800));
+        assert_eq!(t.dos_timepart(), 0);
+        assert_eq!(t.dos_datepart(), 1 | (1 << 5));
+        assert_eq!(t.mtime(), 315_532_800);
+    }
+
+    #[test]
+    fn test_out_of_range_is_clamped() {
+        let t = Timestamp::utc(SystemTime::UNIX_EPOCH);
+        assert_eq!(t.dos_timepart(), 0);
+        assert_eq!(t.dos_datepart(), 1 | (1 << 5));
+        // the extended timestamp keeps the real time
+        assert_eq!(t.mtime(), 0);
+
+        // 2200-01-01
+        let t = Timestamp::utc(SystemTime::UNIX_EPOCH + Duration::from_secs(7_258_118_400));
+        assert_eq!(t.dos_timepart(), (23 << 11) | (59 << 5) | 29);
+        assert_eq!(t.dos_datepart(), 31 | (12 << 5) | (127 << 9));
+        assert_eq!(t.mtime(), i32::MAX);
+    }
 }
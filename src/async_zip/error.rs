@@ -1,21 +1,41 @@
-use std::io;
+use std::{io, path::PathBuf};
 
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum Error {
-    #[error("Year ${0} is not zip/DOS range 1980 - 2107")]
-    InvalidYear(i32),
     #[error("File is too big {0}")]
     FileTooBig(u64),
     #[error("File name is too big (bigger then 65535)")]
     FileNameTooBig,
-    #[error("ZIP Archive too big (over 4GB)")]
-    ArchiveTooBig,
+    #[error("Comment is too big (bigger then 65535)")]
+    CommentTooBig,
     #[error("IO error ${0}")]
     Io(#[from] io::Error),
     #[error("Invalid path - does not contain file name")]
     InvalidPath,
+    #[error("File name of {0:?} is not valid UTF-8")]
+    Encoding(PathBuf),
+    #[error("Invalid archive - {0}")]
+    InvalidArchive(&'static str),
+    #[error("Compression method {0} is not supported")]
+    UnsupportedCompression(u16),
+    #[error("CRC-32 of {0} does not match")]
+    CrcMismatch(String),
+    #[error("{0} is encrypted, a password is required")]
+    PasswordRequired(String),
+    #[error("AES strength {0} is not supported, only AES-256")]
+    UnsupportedEncryption(u8),
+    #[error("Wrong password for {0}")]
+    WrongPassword(String),
+    #[error("Authentication code of {0} does not match")]
+    AuthenticationFailed(String),
+    #[error("Cannot read {0:?}: {1}")]
+    Read(PathBuf, io::Error),
+    #[error("Split archive has more than 65535 volumes")]
+    TooManyVolumes,
+    #[error("Archive stream receiver is gone")]
+    ReceiverGone,
 }
 
 impl From<Error> for io::Error {
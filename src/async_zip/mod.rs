@@ -1,123 +1,395 @@
-use std::{
-    io,
-    path::{Path, PathBuf},
-};
+use std::{io, path::Path, time::SystemTime};
 use tokio::{fs, io::AsyncReadExt};
 
-use crate::async_zip::error::Result;
-pub use crate::async_zip::zip::calc_size;
-use crate::async_zip::zip::{Descriptor, Directory, FileHeader, ToBytes};
+use crate::async_zip::aes::Encryptor;
+use crate::async_zip::date::Timestamp;
+use crate::async_zip::error::{Error, Result};
+use crate::async_zip::policy::SAMPLE_SIZE;
+pub use crate::async_zip::policy::{CompressionPolicy, COMPRESSED_EXTENSIONS};
+pub use crate::async_zip::read::{ZipEntry, ZipReader};
+pub use crate::async_zip::zip::{unix_mode, Compression, SplitChunk, SINGLE_VOLUME_SIGNATURE};
+use crate::async_zip::zip::{
+    Compressor, Descriptor, Directory, FileHeader, ToBytes, Volumes, SPLIT_ARCHIVE_SIGNATURE,
+};
+use crate::walk::Entry;
+#[cfg(test)]
+use crate::{
+    async_zip::zip::path_to_file_name,
+    walk::{walk_directory, NonUtf8Names},
+};
 use futures::{
     channel::mpsc::{channel, Receiver, Sender},
-    SinkExt,
+    future, SinkExt, Stream, StreamExt,
 };
 
+mod aes;
 mod date;
 pub mod error;
+mod policy;
+mod read;
 mod zip;
-pub struct Zipper<P> {
-    files: Box<dyn Iterator<Item = P> + Send>,
+
+/// Comment of an entry from its path.
+type EntryComment = Box<dyn Fn(&Path) -> Option<String> + Send>;
+
+pub struct Zipper {
+    entries: Box<dyn Iterator<Item = Result<Entry>> + Send>,
+    compression: Box<dyn Fn(&Path) -> Compression + Send>,
+    policy: CompressionPolicy,
+    comment: String,
+    entry_comment: EntryComment,
+    fixed_time: Option<SystemTime>,
+    utc: bool,
+    normalize_modes: bool,
+    password: Option<String>,
+    split_size: Option<u64>,
 }
 
-impl<P> Zipper<P>
-where
-    P: AsRef<Path> + Send + Sync + 'static,
-{
-    pub fn from_iter<I>(files: I) -> Self
+impl Zipper {
+    /// Zips the given files, each entry is named by its file name.
+    #[cfg(test)]
+    pub fn from_iter<P, I>(files: I) -> Self
     where
+        P: AsRef<Path>,
         I: Iterator<Item = P> + Send + 'static,
+    {
+        Zipper::from_results(files.map(|path| {
+            Ok(Entry {
+                name: path_to_file_name(&path, NonUtf8Names::Error)?.to_string(),
+                path: path.as_ref().to_owned(),
+                link: None,
+            })
+        }))
+    }
+
+    /// Zips the given entries, keeping their archive names.
+    pub fn from_entries<I>(entries: I) -> Self
+    where
+        I: Iterator<Item = Entry> + Send + 'static,
+    {
+        Zipper::from_results(entries.map(Ok))
+    }
+
+    fn from_results<I>(entries: I) -> Self
+    where
+        I: Iterator<Item = Result<Entry>> + Send + 'static,
     {
         Zipper {
-            files: Box::new(files),
+            entries: Box::new(entries),
+            compression: Box::new(|_| Compression::Store),
+            policy: CompressionPolicy::default(),
+            comment: String::new(),
+            entry_comment: Box::new(|_| None),
+            fixed_time: None,
+            utc: false,
+            normalize_modes: false,
+            password: None,
+            split_size: None,
         }
     }
 
+    /// Compresses every entry with `compression`, entries are stored by default.
+    pub fn compression(self, compression: Compression) -> Self {
+        self.compression_with(move |_| compression)
+    }
+
+    /// Picks the compression of each entry from its path.
+    pub fn compression_with<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Path) -> Compression + Send + 'static,
+    {
+        self.compression = Box::new(f);
+        self
+    }
+
+    /// Stores the files that `policy` picks instead of compressing them.
+    pub fn policy(mut self, policy: CompressionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sets the archive comment.
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = comment.into();
+        self
+    }
+
+    /// Comments each entry with what `f` returns for its path.
+    pub fn entry_comments<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Path) -> Option<String> + Send + 'static,
+    {
+        self.entry_comment = Box::new(f);
+        self
+    }
+
+    /// Stamps every entry with `time` in UTC instead of its local modification
+    /// time, for reproducible archives.
+    pub fn fixed_time(mut self, time: Option<SystemTime>) -> Self {
+        self.fixed_time = time;
+        self
+    }
+
+    /// Writes the DOS modification times in UTC instead of local time, the
+    /// extended timestamp is always in UTC.
+    pub fn utc(mut self, utc: bool) -> Self {
+        self.utc = utc;
+        self
+    }
+
+    /// Records normalized Unix modes instead of the file ones, for
+    /// reproducible archives.
+    pub fn normalize_modes(mut self, normalize: bool) -> Self {
+        self.normalize_modes = normalize;
+        self
+    }
+
+    /// Encrypts the file entries with WinZip AES-256 (AE-2) using `password`.
+    pub fn password(mut self, password: Option<String>) -> Self {
+        self.password = password;
+        self
+    }
+
+    /// Splits the archive in volumes of at most `split_size` bytes, see
+    /// `split_stream`.
+    pub fn split_size(mut self, split_size: Option<u64>) -> Self {
+        self.split_size = split_size;
+        self
+    }
+
     async fn main_loop(
-        files: Box<dyn Iterator<Item = P> + Send>,
-        mut sender: Sender<std::result::Result<Vec<u8>, io::Error>>,
+        self,
+        mut sender: Sender<std::result::Result<SplitChunk, io::Error>>,
     ) -> Result<()> {
         let mut pos: u64 = 0;
-        let mut dir = Directory::new();
+        let mut dir = Directory::new().comment(self.comment);
+        let mut volumes = Volumes::new(self.split_size);
 
+        macro_rules! send_chunks {
+            ($chunks:expr) => {{
+                for chunk in $chunks {
+                    sender
+                        .send(Ok(chunk))
+                        .await
+                        .map_err(|_| Error::ReceiverGone)?;
+                }
+            }};
+        }
+
+        // entry data, split where volumes are full
         macro_rules! send {
             ($data:ident) => {{
                 pos += $data.len() as u64;
-                sender.send(Ok($data)).await.expect("receiver gone");
+                send_chunks!(volumes.data($data)?);
+            }};
+        }
+
+        // a header or descriptor, kept in one volume, evaluates to where it
+        // starts
+        macro_rules! record {
+            ($data:ident) => {{
+                if let Some(next_volume) = volumes.reserve($data.len() as u64)? {
+                    send_chunks!([next_volume]);
+                }
+                let position = volumes.position();
+                send!($data);
+                position
             }};
         }
 
-        for path in files {
-            let mut f = fs::File::open(&path).await?;
-            let meta = f.metadata().await?;
+        // split archives start with their signature
+        if self.split_size.is_some() {
+            let signature = SPLIT_ARCHIVE_SIGNATURE.to_le_bytes().to_vec();
+            record!(signature);
+        }
+
+        let (fixed_time, utc) = (self.fixed_time, self.utc);
+        let normalize_modes = self.normalize_modes;
+        let timestamp = |meta: &std::fs::Metadata| -> Result<Timestamp> {
+            Ok(match fixed_time {
+                Some(time) => Timestamp::utc(time),
+                None => {
+                    let modified = meta.modified()?;
+                    let timestamp = if utc {
+                        Timestamp::utc(modified)
+                    } else {
+                        Timestamp::local(modified)
+                    };
+                    // not every platform records access times
+                    match meta.accessed() {
+                        Ok(accessed) => timestamp.accessed(accessed),
+                        Err(_) => timestamp,
+                    }
+                }
+            })
+        };
+
+        for entry in self.entries {
+            let entry = entry?;
+            let comment = (self.entry_comment)(&entry.path).unwrap_or_default();
+            let read_error = |e| Error::Read(entry.path.clone(), e);
+
+            // directories only have a header and an empty descriptor
+            if entry.is_dir() {
+                let meta = fs::metadata(&entry.path).await.map_err(read_error)?;
+                let file_header =
+                    FileHeader::new(entry.name, timestamp(&meta)?, 0, Compression::Store)?
+                        .mode(unix_mode(&meta, normalize_modes))
+                        .comment(comment);
+                let file_header_bytes = file_header.to_bytes()?;
+                let file_header_offset = record!(file_header_bytes);
+
+                let desc = Descriptor::new(0, 0, 0, file_header.zip64());
+                let desc_bytes = desc.to_bytes()?;
+                record!(desc_bytes);
+                dir.add_entry(file_header, desc, file_header_offset);
+                continue;
+            }
+
+            // links are stored with their target as content
+            if let Some(target) = entry.link_target() {
+                let meta = fs::symlink_metadata(&entry.path)
+                    .await
+                    .map_err(read_error)?;
+                let size = target.len() as u64;
+                let file_header =
+                    FileHeader::new(entry.name, timestamp(&meta)?, size, Compression::Store)?
+                        .mode(unix_mode(&meta, normalize_modes))
+                        .comment(comment);
+                let file_header_bytes = file_header.to_bytes()?;
+                let file_header_offset = record!(file_header_bytes);
+
+                let crc = crc32fast::hash(&target);
+                send!(target);
+                let desc = Descriptor::new(size, size, crc, file_header.zip64());
+                let desc_bytes = desc.to_bytes()?;
+                record!(desc_bytes);
+                dir.add_entry(file_header, desc, file_header_offset);
+                continue;
+            }
+
+            let mut f = fs::File::open(&entry.path).await.map_err(read_error)?;
+            let meta = f.metadata().await.map_err(read_error)?;
+            // the start of the file is read ahead to pick the method
+            let mut sample = Vec::with_capacity(SAMPLE_SIZE);
+            (&mut f)
+                .take(SAMPLE_SIZE as u64)
+                .read_to_end(&mut sample)
+                .await
+                .map_err(read_error)?;
+            let compression = if self.policy.stores(&entry.path, &sample) {
+                Compression::Store
+            } else {
+                (self.compression)(&entry.path)
+            };
             // send header
-            let file_header = FileHeader::new(path, meta.modified()?)?;
+            let file_header =
+                FileHeader::new(entry.name, timestamp(&meta)?, meta.len(), compression)?
+                    .mode(unix_mode(&meta, normalize_modes))
+                    .comment(comment)
+                    .encrypted(self.password.is_some());
             let file_header_bytes = file_header.to_bytes()?;
-            let file_header_offset = pos;
-            send!(file_header_bytes);
+            let file_header_offset = record!(file_header_bytes);
 
             let file_content_offset = pos;
+            let mut file_size: u64 = 0;
             let mut hasher = crc32fast::Hasher::new();
+            let mut compressor = Compressor::new(compression)?;
+            // encrypted data starts with the salt and password verifier
+            let mut encryptor = match &self.password {
+                Some(password) => {
+                    let (encryptor, salt) = Encryptor::new(password)?;
+                    send!(salt);
+                    Some(encryptor)
+                }
+                None => None,
+            };
+            let encrypted = encryptor.is_some();
+            let mut encrypt = |data: Vec<u8>| match &mut encryptor {
+                Some(encryptor) => encryptor.encrypt(data),
+                None => data,
+            };
+            let mut sample = Some(sample);
             loop {
-                let mut data = Vec::with_capacity(8 * 1024);
-                let read = f.read_buf(&mut data).await?;
-                if read == 0 {
+                let data = match sample.take() {
+                    Some(sample) => sample,
+                    None => {
+                        let mut data = Vec::with_capacity(8 * 1024);
+                        f.read_buf(&mut data).await.map_err(read_error)?;
+                        data
+                    }
+                };
+                if data.is_empty() {
                     break;
                 }
+                file_size += data.len() as u64;
                 hasher.update(&data);
-                send!(data);
+                let compressed = encrypt(compressor.compress(data)?);
+                if !compressed.is_empty() {
+                    send!(compressed);
+                }
+            }
+            let compressed = encrypt(compressor.finish()?);
+            send!(compressed);
+            if let Some(encryptor) = encryptor {
+                let auth_code = encryptor.finish();
+                send!(auth_code);
             }
 
-            let file_size = pos - file_content_offset;
-            let crc = hasher.finalize();
-            let desc = Descriptor::new(file_size, crc);
+            let compressed_size = pos - file_content_offset;
+            // AE-2 leaves the CRC-32 out, the authentication code covers the data
+            let crc = if encrypted { 0 } else { hasher.finalize() };
+            let desc = Descriptor::new(compressed_size, file_size, crc, file_header.zip64());
             let desc_bytes = desc.to_bytes()?;
-            send!(desc_bytes);
+            record!(desc_bytes);
             dir.add_entry(file_header, desc, file_header_offset);
         }
-        let directory_bytes = dir.finalize(pos)?;
-        sender
-            .send(Ok(directory_bytes))
-            .await
-            .expect("receiver gone");
+        send_chunks!(dir.finalize(&mut volumes)?);
 
         Ok(())
     }
 
-    pub fn zipped_stream(self) -> Receiver<std::result::Result<Vec<u8>, io::Error>> {
+    /// Streams the archive, the volumes of a split archive one after the
+    /// other.
+    pub fn zipped_stream(self) -> impl Stream<Item = std::result::Result<Vec<u8>, io::Error>> {
+        self.split_stream().filter_map(|chunk| {
+            future::ready(match chunk {
+                Ok(SplitChunk::Data(data)) => Some(Ok(data)),
+                Ok(SplitChunk::NextVolume) => None,
+                Err(e) => Some(Err(e)),
+            })
+        })
+    }
+
+    /// Streams the archive with the ends of its volumes.
+    pub fn split_stream(self) -> Receiver<std::result::Result<SplitChunk, io::Error>> {
         let (mut s, r) = channel(64);
 
         tokio::spawn(async move {
             let sender = s.clone();
-            let res = Zipper::main_loop(self.files, sender).await;
+            let res = self.main_loop(sender).await;
             if let Err(e) = res {
                 s.send(Err(e.into())).await.ok();
             }
         });
         r
     }
-}
 
-impl Zipper<PathBuf> {
-    pub async fn from_directory(
-        path: impl AsRef<Path>,
-    ) -> std::result::Result<Zipper<PathBuf>, io::Error> {
-        let mut files = vec![];
-        let mut dir_listing = fs::read_dir(path).await?;
-        while let Some(entry) = dir_listing.next_entry().await? {
-            if entry.file_type().await?.is_file() {
-                files.push(entry.path())
-            }
-        }
+    /// Zips the directory tree under `path`, entries are named relative to it.
+    #[cfg(test)]
+    pub async fn from_directory(path: impl AsRef<Path>) -> crate::error::Result<Zipper> {
+        let entries = walk_directory(path).await?;
 
-        Ok(Zipper::from_iter(files.into_iter()))
+        Ok(Zipper::from_entries(entries.into_iter()))
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use super::{calc_size, Zipper};
-    use crate::async_zip::error::Result;
+    use super::{zip::calc_size, Compression, Zipper};
+    use crate::error::Result;
+    use crate::test_util::TempDir;
     use futures::StreamExt;
     use std::{
         fs,
@@ -127,9 +399,17 @@ mod tests {
     use tokio::io::AsyncReadExt;
     use zip::ZipArchive;
 
+    /// Number of files directly in `dir`.
+    fn file_count(dir: impl AsRef<Path>) -> usize {
+        fs::read_dir(dir)
+            .expect("cannot read directory")
+            .filter(|e| e.as_ref().unwrap().file_type().unwrap().is_file())
+            .count()
+    }
+
     async fn test_zip<T: Read + Seek>(f: T, dir: impl AsRef<Path>) {
         let mut zip = ZipArchive::new(f).expect("cannot open archive");
-        assert_eq!(zip.len(), 4);
+        assert_eq!(zip.len(), file_count(&dir));
         for i in 0..zip.len() {
             let mut file = zip.by_index(i).expect("entry error");
             println!(
@@ -185,8 +465,12 @@ mod tests {
                 })
             })
             .collect::<Vec<_>>();
-        assert_eq!(files.len(), 4);
-        let expected_size = calc_size(files.iter().map(|&(ref p, s)| (p, s)))?;
+        assert!(!files.is_empty());
+        let expected_size = calc_size(
+            files
+                .iter()
+                .map(|&(ref p, s)| (p.file_name().unwrap().to_string_lossy(), s)),
+        )?;
         let zipper = Zipper::from_iter(files.into_iter().map(|(p, _)| p));
         let mut stream = zipper.zipped_stream();
         let mut f = Cursor::new(Vec::<u8>::new());
@@ -202,4 +486,228 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_zip_nested_directory() -> Result<()> {
+        let dir = PathBuf::from("src");
+        let zipper = Zipper::from_directory(&dir).await?;
+        let mut stream = zipper.zipped_stream();
+        let mut f = Cursor::new(Vec::<u8>::new());
+        while let Some(chunk) = stream.next().await {
+            f.write_all(&(chunk?)).unwrap();
+        }
+
+        f.set_position(0);
+        let mut zip = ZipArchive::new(f).expect("cannot open archive");
+        assert!(zip
+            .by_name("async_zip/")
+            .expect("no directory entry")
+            .is_dir());
+
+        let mut file = zip.by_name("async_zip/zip.rs").expect("no nested entry");
+        let mut content = vec![];
+        file.read_to_end(&mut content).expect("read content error");
+        assert_eq!(content, fs::read(dir.join("async_zip/zip.rs"))?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_zip_deflate() -> Result<()> {
+        let dir = PathBuf::from("src/async_zip");
+        let stored_size = calc_size(
+            fs::read_dir(&dir)?
+                .map(|e| e.unwrap())
+                .filter(|e| e.file_type().unwrap().is_file())
+                .map(|e| {
+                    (
+                        e.file_name().to_string_lossy().into_owned(),
+                        e.metadata().unwrap().len(),
+                    )
+                }),
+        )?;
+        // deflate everything but one entry
+        let zipper = Zipper::from_directory(&dir).await?.compression_with(|p| {
+            if p.ends_with("date.rs") {
+                Compression::Store
+            } else {
+                Compression::Deflate { level: 6 }
+            }
+        });
+        let mut stream = zipper.zipped_stream();
+        let mut f = Cursor::new(Vec::<u8>::new());
+        while let Some(chunk) = stream.next().await {
+            f.write_all(&(chunk?)).unwrap();
+        }
+
+        assert!((f.get_ref().len() as u64) < stored_size);
+
+        f.set_position(0);
+
+        test_zip(f, &dir).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_zip64_many_files() -> Result<()> {
+        let path = PathBuf::from("src/async_zip/date.rs");
+        let len = fs::metadata(&path)?.len();
+        let count = std::u16::MAX as usize + 10;
+
+        let expected_size = calc_size(std::iter::repeat(("date.rs", len)).take(count))?;
+        let zipper = Zipper::from_iter(std::iter::repeat(path.clone()).take(count));
+        let mut stream = zipper.zipped_stream();
+        let mut f = Cursor::new(Vec::<u8>::new());
+        while let Some(chunk) = stream.next().await {
+            f.write_all(&(chunk?)).unwrap();
+        }
+
+        assert_eq!(f.get_ref().len(), expected_size as usize);
+
+        f.set_position(0);
+        let zip = ZipArchive::new(f).expect("cannot open archive");
+        assert_eq!(zip.len(), count);
+
+        Ok(())
+    }
+
+    /// Reads the entries of `buf` from its local headers only, as streaming
+    /// readers do, checking every data descriptor against the entry data.
+    fn read_streaming(mut buf: &[u8]) -> Vec<(String, Vec<u8>)> {
+        use bytes::Buf;
+
+        let mut entries = vec![];
+        loop {
+            let signature = buf.get_u32_le();
+            if signature == 0x02014b50 {
+                // the central directory follows the last entry
+                return entries;
+            }
+            assert_eq!(signature, 0x04034b50, "local header signature");
+            // version needed to extract
+            buf.advance(2);
+            let flags = buf.get_u16_le();
+            assert_ne!(flags & (1 << 3), 0, "sizes follow in a data descriptor");
+            let method = buf.get_u16_le();
+            // last mod file time and date
+            buf.advance(4);
+            assert_eq!(buf.get_u32_le(), 0, "crc-32 is deferred");
+            buf.advance(8);
+            let name_len = buf.get_u16_le() as usize;
+            let extra_len = buf.get_u16_le() as usize;
+            let name = String::from_utf8(buf[..name_len].to_vec()).expect("UTF-8 name");
+            buf.advance(name_len);
+            let mut extra = &buf[..extra_len];
+            let mut zip64 = false;
+            while extra.has_remaining() {
+                let id = extra.get_u16_le();
+                let len = extra.get_u16_le() as usize;
+                zip64 |= id == 0x0001;
+                extra.advance(len);
+            }
+            buf.advance(extra_len);
+
+            // the data ends where the compressed stream does, stored data at
+            // the first descriptor matching it
+            let (content, compressed_size) = match method {
+                8 => {
+                    let mut decoder = flate2::bufread::DeflateDecoder::new(buf);
+                    let mut content = vec![];
+                    decoder.read_to_end(&mut content).expect("deflate stream");
+                    let compressed_size = decoder.total_in() as usize;
+                    (content, compressed_size)
+                }
+                0 => {
+                    let size = (0..buf.len())
+                        .find(|&at| {
+                            buf[at..].starts_with(&0x08074b50u32.to_le_bytes())
+                                && buf[at + 4..at + 8] == crc32fast::hash(&buf[..at]).to_le_bytes()
+                        })
+                        .expect("no data descriptor after stored data");
+                    (buf[..size].to_vec(), size)
+                }
+                method => panic!("unexpected compression method {method}"),
+            };
+            buf.advance(compressed_size);
+
+            assert_eq!(buf.get_u32_le(), 0x08074b50, "data descriptor signature");
+            assert_eq!(
+                buf.get_u32_le(),
+                crc32fast::hash(&content),
+                "crc-32 of {name}"
+            );
+            let (compressed, size) = if zip64 {
+                (buf.get_u64_le(), buf.get_u64_le())
+            } else {
+                (buf.get_u32_le() as u64, buf.get_u32_le() as u64)
+            };
+            assert_eq!(
+                compressed, compressed_size as u64,
+                "compressed size of {name}"
+            );
+            assert_eq!(size, content.len() as u64, "size of {name}");
+
+            entries.push((name, content));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_streaming_conformance() -> Result<()> {
+        let dir = PathBuf::from("src");
+        for compression in [Compression::Store, Compression::Deflate { level: 6 }] {
+            let zipper = Zipper::from_directory(&dir).await?.compression(compression);
+            let mut stream = zipper.zipped_stream();
+            let mut buf = vec![];
+            while let Some(chunk) = stream.next().await {
+                buf.extend_from_slice(&chunk?);
+            }
+
+            let entries = read_streaming(&buf);
+            assert_eq!(
+                entries.len(),
+                crate::walk::walk_directory(&dir).await?.len()
+            );
+            for (name, content) in entries {
+                if name.ends_with('/') {
+                    assert!(content.is_empty());
+                } else {
+                    assert_eq!(content, fs::read(dir.join(&name))?, "content of {name}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_streaming_empty_and_unicode_entries() -> Result<()> {
+        let dir = TempDir::new("streaming");
+        fs::write(dir.join("empty"), "")?;
+        fs::write(dir.join("naïve.txt"), "PK\x07\x08 looks like a descriptor")?;
+
+        for compression in [Compression::Store, Compression::Deflate { level: 6 }] {
+            let zipper = Zipper::from_directory(&dir).await?.compression(compression);
+            let mut stream = zipper.zipped_stream();
+            let mut buf = vec![];
+            while let Some(chunk) = stream.next().await {
+                buf.extend_from_slice(&chunk?);
+            }
+
+            let mut entries = read_streaming(&buf);
+            entries.sort();
+            assert_eq!(
+                entries,
+                [
+                    ("empty".to_owned(), vec![]),
+                    (
+                        "naïve.txt".to_owned(),
+                        b"PK\x07\x08 looks like a descriptor".to_vec()
+                    ),
+                ]
+            );
+        }
+
+        Ok(())
+    }
 }
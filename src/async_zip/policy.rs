@@ -0,0 +1,100 @@
+//! Per-file choice between compressing and storing, for files that are
+//! compressed already.
+
+use std::{collections::HashSet, io::Write, path::Path};
+
+use flate2::write::DeflateEncoder;
+
+/// Size of the block sampled at the start of a file
+pub const SAMPLE_SIZE: usize = 64 * 1024;
+
+/// Extensions of common formats that are compressed already
+pub const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "aac", "apk", "avi", "avif", "br", "bz2", "docx", "epub", "flac", "gif", "gz", "heic",
+    "jar", "jpeg", "jpg", "lz", "lz4", "lzma", "m4a", "mkv", "mov", "mp3", "mp4", "odt", "ogg",
+    "opus", "png", "pptx", "rar", "tbz2", "tgz", "txz", "webm", "webp", "whl", "xlsx", "xz", "zip",
+    "zst",
+];
+
+/// Largest compressed size of a sample, in percent of its size, for the file
+/// to be worth compressing
+const MAX_SAMPLE_RATIO: usize = 95;
+
+/// Decides which files are stored instead of compressed, by extension and by
+/// how well the start of the file compresses. The default policy compresses
+/// every file.
+#[derive(Debug, Clone, Default)]
+pub struct CompressionPolicy {
+    extensions: HashSet<String>,
+    sample: bool,
+}
+
+impl CompressionPolicy {
+    /// Stores the files with one of `extensions`, compared case-insensitively.
+    pub fn store_extensions<I, S>(mut self, extensions: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.extensions.extend(
+            extensions
+                .into_iter()
+                .map(|ext| ext.as_ref().trim_start_matches('.').to_lowercase()),
+        );
+        self
+    }
+
+    /// Stores the files whose first block does not compress.
+    pub fn sample(mut self, sample: bool) -> Self {
+        self.sample = sample;
+        self
+    }
+
+    /// Whether the file at `path`, starting with `sample`, is stored.
+    pub fn stores(&self, path: &Path, sample: &[u8]) -> bool {
+        let stored_extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map_or(false, |ext| self.extensions.contains(&ext.to_lowercase()));
+        stored_extension || (self.sample && !compresses(sample))
+    }
+}
+
+/// Whether the fast deflate of `sample` saves enough to compress the file.
+fn compresses(sample: &[u8]) -> bool {
+    let sample = &sample[..sample.len().min(SAMPLE_SIZE)];
+    if sample.is_empty() {
+        return true;
+    }
+    let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+    match encoder.write_all(sample).and_then(|_| encoder.finish()) {
+        Ok(compressed) => compressed.len() * 100 <= sample.len() * MAX_SAMPLE_RATIO,
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::test_util::noise;
+
+    #[test]
+    fn test_stores() {
+        let policy = CompressionPolicy::default().store_extensions([".JPG", "gz"]);
+        assert!(policy.stores(Path::new("a/photo.jpg"), b""));
+        assert!(policy.stores(Path::new("a/logs.tar.GZ"), b""));
+        assert!(!policy.stores(Path::new("a/jpg"), b""));
+        assert!(!policy.stores(Path::new("a/notes.txt"), b""));
+
+        // xorshift noise does not compress, text does
+        let noise = noise(SAMPLE_SIZE);
+        let text = b"2023-02-01 INFO request served\n".repeat(1000);
+        assert!(!policy.stores(Path::new("noise"), &noise));
+        let policy = policy.sample(true);
+        assert!(policy.stores(Path::new("noise"), &noise));
+        assert!(!policy.stores(Path::new("text"), &text));
+        assert!(!policy.stores(Path::new("empty"), b""));
+    }
+}
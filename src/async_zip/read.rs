@@ -0,0 +1,919 @@
+use std::{
+    io::{self, SeekFrom, Write},
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use bytes::Buf;
+use bzip2::write::BzDecoder;
+use flate2::write::DeflateDecoder;
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+};
+use xz2::{stream::Stream, write::XzDecoder};
+
+use crate::async_zip::aes::{Decryptor, AUTH_CODE_LEN, OVERHEAD, SALT_LEN, VERIFIER_LEN};
+use crate::async_zip::error::{Error, Result};
+use crate::async_zip::zip::{
+    AES_256, AES_EXTRA_FIELD_ID, AE_1, CENTRAL_DIRECTORY_END_SIGNATURE,
+    CENTRAL_DIRECTORY_HEADER_SIGNATURE, COMPRESS_AES, COMPRESS_BZIP2, COMPRESS_DEFLATE,
+    COMPRESS_LZMA, COMPRESS_STORE, COMPRESS_ZSTD, DIRECTORY_END_SIZE, DIRECTORY_ENTRY_SIZE,
+    FILE_HEADER_SIZE, FLAG_UTF8, HOST_UNIX, LOCAL_FILE_HEADER_SIGNATURE, LZMA_ALONE_HEADER_SIZE,
+    LZMA_PROPERTIES_SIZE, S_IFLNK, S_IFMT, UNICODE_PATH_ID, UNICODE_PATH_VERSION,
+    ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIGNATURE, ZIP64_CENTRAL_DIRECTORY_END_SIGNATURE,
+    ZIP64_DIRECTORY_END_LOCATOR_SIZE, ZIP64_DIRECTORY_END_SIZE, ZIP64_EXTRA_FIELD_ID,
+};
+
+const READ_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Upper half of code page 437, the encoding of names without the UTF-8 flag
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+fn decode_cp437(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            0..=0x7f => b as char,
+            _ => CP437_HIGH
+                .chars()
+                .nth((b - 0x80) as usize)
+                .unwrap_or('\u{fffd}'),
+        })
+        .collect()
+}
+
+/// An entry of the central directory.
+#[derive(Debug, Clone)]
+pub struct ZipEntry {
+    pub name: String,
+    pub compression: u16,
+    pub crc: u32,
+    pub compressed_size: u64,
+    pub size: u64,
+    /// Offset of the local file header
+    pub offset: u64,
+    /// Unix mode with its file type bits, when made on Unix
+    pub mode: Option<u32>,
+    pub comment: String,
+    /// WinZip AES encryption, `compression` is then the actual method
+    pub encryption: Option<Encryption>,
+}
+
+/// WinZip AES encryption of an entry.
+#[derive(Debug, Clone, Copy)]
+pub struct Encryption {
+    /// AE-1 or AE-2
+    pub version: u16,
+    /// Key size, 1 - 3 for AES-128, AES-192 and AES-256
+    pub strength: u8,
+}
+
+impl ZipEntry {
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+
+    /// Whether the entry is a link, its content is the link target.
+    pub fn is_symlink(&self) -> bool {
+        self.mode.map_or(false, |mode| mode & S_IFMT == S_IFLNK)
+    }
+}
+
+/// Location of the central directory, from the (ZIP64) end of central
+/// directory record.
+struct CentralDirectory {
+    number_of_files: u64,
+    dir_size: u64,
+    dir_offset: u64,
+    comment: String,
+}
+
+/// Reads archives written by `Zipper`, split or not.
+pub struct ZipReader<R> {
+    reader: R,
+    entries: Vec<ZipEntry>,
+    comment: String,
+    password: Option<String>,
+}
+
+impl ZipReader<VolumeReader> {
+    /// Opens the archive at `path`, with the volumes before it when it ends
+    /// a split archive.
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let reader = VolumeReader::open(path).await?;
+        let starts = reader.starts[..reader.files.len()].to_vec();
+        ZipReader::with_volumes(reader, &starts).await
+    }
+}
+
+impl<R> ZipReader<R>
+where
+    R: AsyncRead + AsyncSeek + Unpin,
+{
+    /// Reads an archive that is not split.
+    #[cfg(test)]
+    pub async fn new(reader: R) -> Result<Self> {
+        ZipReader::with_volumes(reader, &[0]).await
+    }
+
+    /// Reads the volumes of a split archive one after the other in `reader`,
+    /// the volumes start at `starts`.
+    async fn with_volumes(mut reader: R, starts: &[u64]) -> Result<Self> {
+        let dir = find_central_directory(&mut reader, starts).await?;
+        let buf = read_at(&mut reader, dir.dir_offset, dir.dir_size as usize).await?;
+        let entries = parse_central_directory(&buf, dir.number_of_files, starts)?;
+
+        Ok(ZipReader {
+            reader,
+            entries,
+            comment: dir.comment,
+            password: None,
+        })
+    }
+
+    /// Password of the encrypted entries.
+    pub fn password(mut self, password: Option<String>) -> Self {
+        self.password = password;
+        self
+    }
+
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// Archive comment.
+    pub fn comment(&self) -> &str {
+        &self.comment
+    }
+
+    /// Streams the uncompressed content of `entry` to `out`, verifying its
+    /// size and CRC-32.
+    pub async fn read_entry<W>(&mut self, entry: &ZipEntry, out: &mut W) -> Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let header = read_at(&mut self.reader, entry.offset, FILE_HEADER_SIZE as usize).await?;
+        let mut h = &header[..];
+        if h.get_u32_le() != LOCAL_FILE_HEADER_SIGNATURE {
+            return Err(Error::InvalidArchive("bad local file header signature"));
+        }
+        h.advance(22);
+        let name_len = h.get_u16_le() as u64;
+        let extra_len = h.get_u16_le() as u64;
+
+        let data_offset = entry.offset + FILE_HEADER_SIZE as u64 + name_len + extra_len;
+        self.reader.seek(SeekFrom::Start(data_offset)).await?;
+
+        let mut decompressor = Decompressor::new(entry.compression)?;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut size: u64 = 0;
+        let mut remaining = entry.compressed_size;
+        // encrypted data is between the salt and password verifier and the
+        // authentication code
+        let mut decryptor = match entry.encryption {
+            Some(encryption) => {
+                if encryption.strength != AES_256 {
+                    return Err(Error::UnsupportedEncryption(encryption.strength));
+                }
+                let password = self
+                    .password
+                    .as_deref()
+                    .ok_or_else(|| Error::PasswordRequired(entry.name.clone()))?;
+                remaining = remaining
+                    .checked_sub(OVERHEAD)
+                    .ok_or(Error::InvalidArchive("truncated encrypted entry"))?;
+                let mut header = vec![0; SALT_LEN + VERIFIER_LEN];
+                self.reader.read_exact(&mut header).await?;
+                Some(Decryptor::new(password, &header, &entry.name)?)
+            }
+            None => None,
+        };
+        while remaining > 0 {
+            let mut data = vec![0; remaining.min(READ_CHUNK_SIZE) as usize];
+            self.reader.read_exact(&mut data).await?;
+            remaining -= data.len() as u64;
+            if let Some(decryptor) = &mut decryptor {
+                data = decryptor.decrypt(data);
+            }
+
+            let data = decompressor.decompress(data)?;
+            size += data.len() as u64;
+            hasher.update(&data);
+            out.write_all(&data).await?;
+        }
+        let data = decompressor.finish()?;
+        size += data.len() as u64;
+        hasher.update(&data);
+        out.write_all(&data).await?;
+        out.flush().await?;
+
+        if let Some(decryptor) = decryptor {
+            let mut auth_code = [0; AUTH_CODE_LEN];
+            self.reader.read_exact(&mut auth_code).await?;
+            if !decryptor.verify(&auth_code) {
+                return Err(Error::AuthenticationFailed(entry.name.clone()));
+            }
+        }
+        if size != entry.size {
+            return Err(Error::InvalidArchive("entry size does not match"));
+        }
+        // AE-2 leaves the CRC-32 out
+        let has_crc = entry.encryption.map_or(true, |e| e.version == AE_1);
+        if has_crc && hasher.finalize() != entry.crc {
+            return Err(Error::CrcMismatch(entry.name.clone()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Size of the ZIP LZMA header: SDK version, properties size and the
+/// properties
+const LZMA_HEADER_SIZE: usize = 9;
+
+/// The volumes of a split archive, read as one stream. An archive that is not
+/// split is a single volume.
+pub struct VolumeReader {
+    files: Vec<fs::File>,
+    /// Offset of each volume in the stream, then the stream length
+    starts: Vec<u64>,
+    /// Volume being read
+    current: usize,
+    /// Offset to seek to in the current volume before reading, and whether
+    /// the seek started
+    seek: Option<(u64, bool)>,
+    pos: u64,
+}
+
+impl VolumeReader {
+    /// Opens `path`, with the volumes before it, .z01, .z02, ..., when it
+    /// ends a split archive.
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let open = |path: PathBuf| async move {
+            fs::File::open(&path)
+                .await
+                .map_err(|e| Error::Read(path, e))
+        };
+        let mut last = open(path.to_owned()).await?;
+        let (_, end) = find_directory_end(&mut last).await?;
+        // number of this disk, the last one
+        let disks = (&end[4..]).get_u16_le() as u32 + 1;
+
+        let mut files = Vec::with_capacity(disks as usize);
+        for volume in 1..disks {
+            files.push(open(path.with_extension(format!("z{volume:02}"))).await?);
+        }
+        files.push(last);
+        let mut starts = vec![0];
+        for file in &files {
+            let len = file.metadata().await?.len();
+            starts.push(starts[starts.len() - 1] + len);
+        }
+
+        Ok(VolumeReader {
+            files,
+            starts,
+            current: 0,
+            seek: Some((0, false)),
+            pos: 0,
+        })
+    }
+}
+
+impl AsyncRead for VolumeReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.current < this.files.len() && buf.remaining() > 0 {
+            let mut file = Pin::new(&mut this.files[this.current]);
+            if let Some((offset, started)) = &mut this.seek {
+                if !*started {
+                    file.as_mut().start_seek(SeekFrom::Start(*offset))?;
+                    *started = true;
+                }
+                ready!(file.poll_complete(cx))?;
+                this.seek = None;
+                continue;
+            }
+
+            let filled = buf.filled().len();
+            ready!(file.poll_read(cx, buf))?;
+            let read = buf.filled().len() - filled;
+            if read > 0 {
+                this.pos += read as u64;
+                return Poll::Ready(Ok(()));
+            }
+            // the volume is over, the next one is read from its start
+            this.current += 1;
+            this.seek = Some((0, false));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for VolumeReader {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        let len = this.starts[this.files.len()];
+        let pos = match position {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => this.pos.checked_add_signed(delta),
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"))?;
+
+        // the volume holding `pos`, the last one past the end
+        let volume = this.starts[1..]
+            .iter()
+            .position(|&end| pos < end)
+            .unwrap_or(this.files.len() - 1);
+        this.current = volume;
+        this.seek = Some((pos - this.starts[volume], false));
+        this.pos = pos;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.pos))
+    }
+}
+
+/// Streaming decompressor for one entry, fed chunk by chunk.
+enum Decompressor {
+    Store,
+    Deflate(DeflateDecoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Decoder<'static, Vec<u8>>),
+    Bzip2(BzDecoder<Vec<u8>>),
+    /// The LZMA header, until all of it is read
+    LzmaHeader(Vec<u8>),
+    Lzma(XzDecoder<Vec<u8>>),
+}
+
+impl Decompressor {
+    fn new(method: u16) -> Result<Self> {
+        match method {
+            COMPRESS_STORE => Ok(Decompressor::Store),
+            COMPRESS_DEFLATE => Ok(Decompressor::Deflate(DeflateDecoder::new(Vec::new()))),
+            COMPRESS_ZSTD => Ok(Decompressor::Zstd(zstd::stream::write::Decoder::new(
+                Vec::new(),
+            )?)),
+            COMPRESS_BZIP2 => Ok(Decompressor::Bzip2(BzDecoder::new(Vec::new()))),
+            COMPRESS_LZMA => Ok(Decompressor::LzmaHeader(Vec::new())),
+            other => Err(Error::UnsupportedCompression(other)),
+        }
+    }
+
+    /// Decompresses `data`, returning the output that is ready so far.
+    fn decompress(&mut self, mut data: Vec<u8>) -> io::Result<Vec<u8>> {
+        if let Decompressor::LzmaHeader(header) = self {
+            let take = data.len().min(LZMA_HEADER_SIZE - header.len());
+            header.extend(data.drain(..take));
+            if header.len() < LZMA_HEADER_SIZE {
+                return Ok(Vec::new());
+            }
+            *self = Decompressor::Lzma(lzma_decoder(header)?);
+        }
+
+        match self {
+            Decompressor::Store => Ok(data),
+            Decompressor::Deflate(decoder) => {
+                decoder.write_all(&data)?;
+                Ok(std::mem::take(decoder.get_mut()))
+            }
+            Decompressor::Zstd(decoder) => {
+                decoder.write_all(&data)?;
+                Ok(std::mem::take(decoder.get_mut()))
+            }
+            Decompressor::Bzip2(decoder) => {
+                decoder.write_all(&data)?;
+                Ok(std::mem::take(decoder.get_mut()))
+            }
+            Decompressor::LzmaHeader(_) => unreachable!("header is complete"),
+            Decompressor::Lzma(decoder) => {
+                decoder.write_all(&data)?;
+                Ok(std::mem::take(decoder.get_mut()))
+            }
+        }
+    }
+
+    /// Flushes the remaining decompressed output.
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Decompressor::Store => Ok(Vec::new()),
+            Decompressor::Deflate(decoder) => decoder.finish(),
+            Decompressor::Zstd(mut decoder) => {
+                decoder.flush()?;
+                Ok(decoder.into_inner())
+            }
+            Decompressor::Bzip2(mut decoder) => decoder.finish(),
+            Decompressor::LzmaHeader(_) => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated LZMA header",
+            )),
+            Decompressor::Lzma(mut decoder) => decoder.finish(),
+        }
+    }
+}
+
+/// .lzma decoder for the data after the ZIP LZMA `header`, fed the .lzma
+/// header with its properties and an unknown uncompressed size.
+fn lzma_decoder(header: &[u8]) -> io::Result<XzDecoder<Vec<u8>>> {
+    if (&header[2..4]).get_u16_le() != LZMA_PROPERTIES_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid LZMA properties",
+        ));
+    }
+    let mut alone_header = Vec::with_capacity(LZMA_ALONE_HEADER_SIZE);
+    alone_header.extend_from_slice(&header[4..]);
+    alone_header.extend_from_slice(&u64::MAX.to_le_bytes());
+
+    let stream = Stream::new_lzma_decoder(u64::MAX)?;
+    let mut decoder = XzDecoder::new_stream(Vec::new(), stream);
+    decoder.write_all(&alone_header)?;
+    Ok(decoder)
+}
+
+async fn read_at<R>(reader: &mut R, offset: u64, len: usize) -> Result<Vec<u8>>
+where
+    R: AsyncRead + AsyncSeek + Unpin,
+{
+    let mut buf = vec![0; len];
+    reader.seek(SeekFrom::Start(offset)).await?;
+    reader.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+fn ensure_len(buf: &[u8], len: usize) -> Result<()> {
+    if buf.len() < len {
+        return Err(Error::InvalidArchive("truncated record"));
+    }
+    Ok(())
+}
+
+/// Offset of the end of central directory record, and the record up to the
+/// end of the archive.
+async fn find_directory_end<R>(reader: &mut R) -> Result<(u64, Vec<u8>)>
+where
+    R: AsyncRead + AsyncSeek + Unpin,
+{
+    let len = reader.seek(SeekFrom::End(0)).await?;
+    let tail_len = len.min(DIRECTORY_END_SIZE as u64 + std::u16::MAX as u64);
+    let tail_offset = len - tail_len;
+    let mut tail = read_at(reader, tail_offset, tail_len as usize).await?;
+
+    let signature = CENTRAL_DIRECTORY_END_SIGNATURE.to_le_bytes();
+    let pos = (0..(tail.len() + 1).saturating_sub(DIRECTORY_END_SIZE as usize))
+        .rev()
+        .find(|&i| tail[i..i + 4] == signature)
+        .ok_or(Error::InvalidArchive("end of central directory not found"))?;
+
+    Ok((tail_offset + pos as u64, tail.split_off(pos)))
+}
+
+/// Offset in the stream of `offset` in the volume `disk`, volumes start at
+/// `starts`.
+fn volume_offset(starts: &[u64], disk: u32, offset: u64) -> Result<u64> {
+    starts
+        .get(disk as usize)
+        .map(|start| start + offset)
+        .ok_or(Error::InvalidArchive("disk number out of range"))
+}
+
+/// Finds the end of central directory record, it is the last record of the
+/// archive followed by a comment of at most 65535 bytes.
+async fn find_central_directory<R>(reader: &mut R, starts: &[u64]) -> Result<CentralDirectory>
+where
+    R: AsyncRead + AsyncSeek + Unpin,
+{
+    let (end_offset, end) = find_directory_end(reader).await?;
+    let mut end = &end[4..];
+    let disk = end.get_u16_le() as u32;
+    if disk as usize + 1 != starts.len() {
+        return Err(Error::InvalidArchive(
+            "volumes of the split archive are missing",
+        ));
+    }
+    let dir_disk = end.get_u16_le() as u32;
+    // number of files on this disk
+    end.advance(2);
+    let number_of_files = end.get_u16_le();
+    let dir_size = end.get_u32_le();
+    let dir_offset = end.get_u32_le();
+    let comment_len = (end.get_u16_le() as usize).min(end.len());
+    // the archive comment has no UTF-8 flag
+    let comment = match std::str::from_utf8(&end[..comment_len]) {
+        Ok(comment) => comment.to_owned(),
+        Err(_) => decode_cp437(&end[..comment_len]),
+    };
+
+    let zip64 = number_of_files == std::u16::MAX
+        || dir_size == std::u32::MAX
+        || dir_offset == std::u32::MAX;
+    if zip64 && end_offset >= ZIP64_DIRECTORY_END_LOCATOR_SIZE as u64 {
+        let locator_offset = end_offset - ZIP64_DIRECTORY_END_LOCATOR_SIZE as u64;
+        let locator = read_at(
+            reader,
+            locator_offset,
+            ZIP64_DIRECTORY_END_LOCATOR_SIZE as usize,
+        )
+        .await?;
+        let mut locator = &locator[..];
+        if locator.get_u32_le() == ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIGNATURE {
+            let zip64_end_disk = locator.get_u32_le();
+            let zip64_end_offset = volume_offset(starts, zip64_end_disk, locator.get_u64_le())?;
+            let dir = read_zip64_directory_end(reader, zip64_end_offset, starts).await?;
+            return Ok(CentralDirectory { comment, ..dir });
+        }
+    }
+
+    Ok(CentralDirectory {
+        number_of_files: number_of_files as u64,
+        dir_size: dir_size as u64,
+        dir_offset: volume_offset(starts, dir_disk, dir_offset as u64)?,
+        comment,
+    })
+}
+
+async fn read_zip64_directory_end<R>(
+    reader: &mut R,
+    offset: u64,
+    starts: &[u64],
+) -> Result<CentralDirectory>
+where
+    R: AsyncRead + AsyncSeek + Unpin,
+{
+    let end = read_at(reader, offset, ZIP64_DIRECTORY_END_SIZE as usize).await?;
+    let mut end = &end[..];
+    if end.get_u32_le() != ZIP64_CENTRAL_DIRECTORY_END_SIGNATURE {
+        return Err(Error::InvalidArchive("bad zip64 end of central directory"));
+    }
+    // record size, versions, disk number
+    end.advance(16);
+    let dir_disk = end.get_u32_le();
+    // number of files on this disk
+    end.advance(8);
+    let number_of_files = end.get_u64_le();
+    let dir_size = end.get_u64_le();
+    let dir_offset = volume_offset(starts, dir_disk, end.get_u64_le())?;
+
+    Ok(CentralDirectory {
+        number_of_files,
+        dir_size,
+        dir_offset,
+        comment: String::new(),
+    })
+}
+
+fn parse_central_directory(
+    mut buf: &[u8],
+    number_of_files: u64,
+    starts: &[u64],
+) -> Result<Vec<ZipEntry>> {
+    let mut entries = Vec::with_capacity(number_of_files.min(std::u16::MAX as u64) as usize);
+
+    for _ in 0..number_of_files {
+        ensure_len(buf, DIRECTORY_ENTRY_SIZE as usize)?;
+        if buf.get_u32_le() != CENTRAL_DIRECTORY_HEADER_SIGNATURE {
+            return Err(Error::InvalidArchive("bad central directory signature"));
+        }
+        let made_by = buf.get_u16_le();
+        // version needed to extract
+        buf.advance(2);
+        let flags = buf.get_u16_le();
+        let mut compression = buf.get_u16_le();
+        // last mod file time + date
+        buf.advance(4);
+        let crc = buf.get_u32_le();
+        let mut compressed_size = buf.get_u32_le() as u64;
+        let mut size = buf.get_u32_le() as u64;
+        let name_len = buf.get_u16_le() as usize;
+        let extra_len = buf.get_u16_le() as usize;
+        let comment_len = buf.get_u16_le() as usize;
+        let mut disk = buf.get_u16_le() as u32;
+        // internal file attributes
+        buf.advance(2);
+        let external_attributes = buf.get_u32_le();
+        let mut offset = buf.get_u32_le() as u64;
+
+        ensure_len(buf, name_len + extra_len + comment_len)?;
+        let decode = |field: &[u8]| {
+            if flags & FLAG_UTF8 != 0 {
+                String::from_utf8_lossy(field).into_owned()
+            } else {
+                decode_cp437(field)
+            }
+        };
+        let name_field = &buf[..name_len];
+        let mut name = decode(name_field);
+        buf.advance(name_len);
+
+        // ZIP64 extended information holds the values marked as 0xFFFFFFFF,
+        // the Unicode path replaces the name unless the name changed since
+        let mut extra = &buf[..extra_len];
+        let mut encryption = None;
+        while extra.len() >= 4 {
+            let id = extra.get_u16_le();
+            let len = extra.get_u16_le() as usize;
+            ensure_len(extra, len)?;
+            let mut field = &extra[..len];
+            extra.advance(len);
+            if id == AES_EXTRA_FIELD_ID && compression == COMPRESS_AES && len >= 7 {
+                let version = field.get_u16_le();
+                // vendor ID
+                field.advance(2);
+                let strength = field.get_u8();
+                compression = field.get_u16_le();
+                encryption = Some(Encryption { version, strength });
+                continue;
+            }
+            if id == UNICODE_PATH_ID && len >= 5 {
+                let version = field.get_u8();
+                if version == UNICODE_PATH_VERSION
+                    && field.get_u32_le() == crc32fast::hash(name_field)
+                {
+                    name = String::from_utf8_lossy(field).into_owned();
+                }
+                continue;
+            }
+            if id != ZIP64_EXTRA_FIELD_ID {
+                continue;
+            }
+            for value in [&mut size, &mut compressed_size, &mut offset] {
+                if *value == std::u32::MAX as u64 {
+                    ensure_len(field, 8)?;
+                    *value = field.get_u64_le();
+                }
+            }
+            if disk == std::u16::MAX as u32 {
+                ensure_len(field, 4)?;
+                disk = field.get_u32_le();
+            }
+        }
+        buf.advance(extra_len);
+        let comment = decode(&buf[..comment_len]);
+        buf.advance(comment_len);
+
+        entries.push(ZipEntry {
+            name,
+            compression,
+            crc,
+            compressed_size,
+            size,
+            offset: volume_offset(starts, disk, offset)?,
+            mode: (made_by & 0xFF00 == HOST_UNIX)
+                .then_some(external_attributes >> 16)
+                .filter(|&mode| mode != 0),
+            comment,
+            encryption,
+        });
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Cursor, path::PathBuf};
+
+    use futures::StreamExt;
+
+    use super::{decode_cp437, ZipReader};
+    use crate::{
+        async_zip::{
+            error::{Error, Result},
+            Compression, CompressionPolicy, Zipper,
+        },
+        test_util::{noise, TempDir},
+        walk::Entry,
+    };
+
+    async fn zip_to_vec(zipper: Zipper) -> Result<Vec<u8>> {
+        let mut stream = zipper.zipped_stream();
+        let mut buf = vec![];
+        while let Some(chunk) = stream.next().await {
+            buf.extend_from_slice(&chunk?);
+        }
+        Ok(buf)
+    }
+
+    #[tokio::test]
+    async fn test_read_entries() -> crate::error::Result<()> {
+        let dir = PathBuf::from("src");
+        let zipper = Zipper::from_directory(&dir).await?.compression_with(|p| {
+            if p.ends_with("mod.rs") {
+                Compression::Store
+            } else {
+                Compression::Deflate { level: 6 }
+            }
+        });
+        let buf = zip_to_vec(zipper).await?;
+
+        let mut reader = ZipReader::new(Cursor::new(buf)).await?;
+        let entries = reader.entries().to_vec();
+        assert!(entries.iter().any(|e| e.name == "async_zip/" && e.is_dir()));
+
+        for entry in entries.iter().filter(|e| !e.is_dir()) {
+            let mut content = vec![];
+            reader.read_entry(entry, &mut content).await?;
+            assert_eq!(content, fs::read(dir.join(&entry.name))?);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_crc_mismatch() -> Result<()> {
+        let path = PathBuf::from("src/async_zip/date.rs");
+        let mut buf = zip_to_vec(Zipper::from_iter(std::iter::once(path))).await?;
+        // flip a byte of the stored content, right after the local header and
+        // its extended timestamp
+        buf[30 + "date.rs".len() + 13] ^= 0xff;
+
+        let mut reader = ZipReader::new(Cursor::new(buf)).await?;
+        let entry = reader.entries()[0].clone();
+        let res = reader.read_entry(&entry, &mut vec![]).await;
+        assert!(matches!(res, Err(Error::CrcMismatch(name)) if name == "date.rs"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_utf8_names() -> Result<()> {
+        let entry = |name: &str| Entry {
+            path: PathBuf::from("src/async_zip/date.rs"),
+            name: name.to_owned(),
+            link: None,
+        };
+        let zipper = Zipper::from_entries([entry("date.rs"), entry("日付.rs")].into_iter());
+        let mut buf = zip_to_vec(zipper).await?;
+
+        // only the non-ASCII name is flagged as UTF-8
+        let flags = |buf: &[u8], at: usize| u16::from_le_bytes([buf[at + 6], buf[at + 7]]);
+        assert_eq!(flags(&buf, 0) & (1 << 11), 0);
+        let reader = ZipReader::new(Cursor::new(buf.clone())).await?;
+        let names = reader
+            .entries()
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["date.rs", "日付.rs"]);
+        let second = reader.entries()[1].offset as usize;
+        assert_ne!(flags(&buf, second) & (1 << 11), 0);
+
+        // readers ignoring the flag still find the name in the Unicode path
+        // field of the central directory
+        let central = buf.len() - 22 - (46 * 2 + 9 * 2 + 9 + "日付.rs".len() * 2 + "date.rs".len());
+        let second_central = central + 46 + 9 + "date.rs".len();
+        assert_eq!(&buf[second_central..second_central + 4], b"PK\x01\x02");
+        let flags_at = second_central + 8;
+        let cleared = flags(&buf, second_central + 2) & !(1 << 11);
+        buf[flags_at..flags_at + 2].copy_from_slice(&cleared.to_le_bytes());
+        let reader = ZipReader::new(Cursor::new(buf)).await?;
+        assert_eq!(reader.entries()[1].name, "日付.rs");
+
+        assert_eq!(decode_cp437(b"caf\x82 \xe1"), "café ß");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_comments() -> Result<()> {
+        let zipper = Zipper::from_iter(
+            ["src/async_zip/date.rs", "src/async_zip/error.rs"]
+                .into_iter()
+                .map(PathBuf::from),
+        )
+        .comment("build 1234")
+        .entry_comments(|path| {
+            path.ends_with("date.rs")
+                .then(|| format!("from {path:?} ✓"))
+        });
+        let buf = zip_to_vec(zipper).await?;
+
+        let reader = ZipReader::new(Cursor::new(buf.clone())).await?;
+        assert_eq!(reader.comment(), "build 1234");
+        let comments = reader
+            .entries()
+            .iter()
+            .map(|e| e.comment.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(comments, ["from \"src/async_zip/date.rs\" ✓", ""]);
+
+        let mut zip = zip::ZipArchive::new(Cursor::new(buf)).expect("cannot open archive");
+        assert_eq!(zip.comment(), b"build 1234");
+        assert_eq!(zip.by_index(1).expect("entry error").name(), "error.rs");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_compression_methods() -> Result<()> {
+        let dir = PathBuf::from("src/async_zip");
+        for (compression, method) in [
+            (Compression::Zstd { level: 3 }, 93),
+            (Compression::Bzip2 { level: 9 }, 12),
+            (Compression::Lzma { level: 6 }, 14),
+        ] {
+            let zipper = Zipper::from_directory(&dir)
+                .await
+                .expect("cannot walk directory")
+                .compression(compression);
+            let buf = zip_to_vec(zipper).await?;
+
+            let mut reader = ZipReader::new(Cursor::new(buf)).await?;
+            let entries = reader.entries().to_vec();
+            for entry in entries.iter().filter(|e| !e.is_dir()) {
+                assert_eq!(entry.compression, method);
+                let mut content = vec![];
+                reader.read_entry(entry, &mut content).await?;
+                assert_eq!(content, fs::read(dir.join(&entry.name))?);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_policy_stored_entries() -> Result<()> {
+        let root = TempDir::new("policy");
+        let noise = noise(100_000);
+        let text = b"2023-02-01 INFO request served\n".repeat(5000);
+        fs::write(root.join("noise.bin"), &noise)?;
+        fs::write(root.join("photo.JPG"), &text)?;
+        fs::write(root.join("server.log"), &text)?;
+
+        let policy = CompressionPolicy::default()
+            .store_extensions(["jpg"])
+            .sample(true);
+        let zipper = Zipper::from_directory(&root)
+            .await
+            .expect("cannot walk directory")
+            .compression(Compression::Deflate { level: 6 })
+            .policy(policy);
+        let buf = zip_to_vec(zipper).await?;
+
+        let mut reader = ZipReader::new(Cursor::new(buf)).await?;
+        let entries = reader.entries().to_vec();
+        for (name, method) in [("noise.bin", 0), ("photo.JPG", 0), ("server.log", 8)] {
+            let entry = entries.iter().find(|e| e.name == name).expect("no entry");
+            assert_eq!(entry.compression, method, "{name}");
+            let mut content = vec![];
+            reader.read_entry(entry, &mut content).await?;
+            assert_eq!(content, fs::read(root.join(name))?);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_encrypted() -> Result<()> {
+        let dir = PathBuf::from("src/async_zip");
+        let zipper = Zipper::from_directory(&dir)
+            .await
+            .expect("cannot walk directory")
+            .compression_with(|p| {
+                if p.ends_with("date.rs") {
+                    Compression::Store
+                } else {
+                    Compression::Deflate { level: 6 }
+                }
+            })
+            .password(Some("secret".to_owned()));
+        let buf = zip_to_vec(zipper).await?;
+        // the content is not readable in the archive
+        let content = fs::read(dir.join("zip.rs"))?;
+        assert!(!buf.windows(32).any(|w| w == &content[..32]));
+
+        let mut reader = ZipReader::new(Cursor::new(buf.clone()))
+            .await?
+            .password(Some("secret".to_owned()));
+        let entries = reader.entries().to_vec();
+        for entry in entries.iter().filter(|e| !e.is_dir()) {
+            assert_eq!(entry.encryption.map(|e| e.version), Some(2));
+            let mut content = vec![];
+            reader.read_entry(entry, &mut content).await?;
+            assert_eq!(content, fs::read(dir.join(&entry.name))?);
+        }
+
+        let entry = entries.iter().find(|e| !e.is_dir()).expect("no file entry");
+        let mut reader = ZipReader::new(Cursor::new(buf)).await?;
+        let res = reader.read_entry(entry, &mut vec![]).await;
+        assert!(matches!(res, Err(Error::PasswordRequired(_))));
+        let mut reader = reader.password(Some("guess".to_owned()));
+        let res = reader.read_entry(entry, &mut vec![]).await;
+        // the 2-byte verifier lets 1 in 65536 wrong passwords through
+        assert!(matches!(
+            res,
+            Err(Error::WrongPassword(_) | Error::AuthenticationFailed(_))
+        ));
+
+        Ok(())
+    }
+}
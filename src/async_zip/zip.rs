@@ -1,48 +1,380 @@
+use std::io::{self, Write};
+#[cfg(test)]
 use std::{borrow::Cow, path::Path};
 
 use bytes::{BufMut, BytesMut};
+use bzip2::write::BzEncoder;
+use flate2::write::DeflateEncoder;
+use xz2::{
+    stream::{LzmaOptions, Stream},
+    write::XzEncoder,
+};
 
 use crate::async_zip::error::Result;
-use crate::async_zip::{date::Timestamp, error::Error};
+use crate::async_zip::{aes, date::Timestamp, error::Error};
+#[cfg(test)]
+use crate::walk::NonUtf8Names;
 
-const DIRECTORY_END_SIZE: u32 = 22;
-const FILE_HEADER_SIZE: u32 = 30;
+pub const DIRECTORY_END_SIZE: u32 = 22;
+pub const FILE_HEADER_SIZE: u32 = 30;
 const DATA_DESCRIPTOR_SIZE: u32 = 16;
-const DIRECTORY_ENTRY_SIZE: u32 = 46;
-
-const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x04034b50;
-const CENTRAL_DIRECTORY_HEADER_SIGNATURE: u32 = 0x02014b50;
-const CENTRAL_DIRECTORY_END_SIGNATURE: u32 = 0x06054b50;
-const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b;
+pub const DIRECTORY_ENTRY_SIZE: u32 = 46;
+const ZIP64_DATA_DESCRIPTOR_SIZE: u32 = 24;
+const ZIP64_LOCAL_EXTRA_SIZE: u32 = 20;
+/// Extended timestamp with modification and access times
+const TIMESTAMP_LOCAL_EXTRA_SIZE: u32 = 13;
+/// Extended timestamp with the modification time only
+const TIMESTAMP_CENTRAL_EXTRA_SIZE: u32 = 9;
+pub const ZIP64_DIRECTORY_END_SIZE: u32 = 56;
+pub const ZIP64_DIRECTORY_END_LOCATOR_SIZE: u32 = 20;
+
+pub const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x04034b50;
+pub const CENTRAL_DIRECTORY_HEADER_SIGNATURE: u32 = 0x02014b50;
+pub const CENTRAL_DIRECTORY_END_SIGNATURE: u32 = 0x06054b50;
+pub const ZIP64_CENTRAL_DIRECTORY_END_SIGNATURE: u32 = 0x06064b50;
+pub const ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+/// First bytes of a split archive
+pub const SPLIT_ARCHIVE_SIGNATURE: u32 = 0x08074b50;
+/// Replaces the split archive signature when the archive fits one volume
+pub const SINGLE_VOLUME_SIGNATURE: u32 = 0x30304b50;
+
+pub const ZIP64_EXTRA_FIELD_ID: u16 = 0x0001;
+/// Info-ZIP extended timestamp, Unix times in UTC
+pub const EXTENDED_TIMESTAMP_ID: u16 = 0x5455;
+/// Info-ZIP Unicode path, the UTF-8 name for readers that ignore the flag
+pub const UNICODE_PATH_ID: u16 = 0x7075;
+pub const UNICODE_PATH_VERSION: u8 = 1;
+/// WinZip AES encryption, the actual compression method is in the field
+pub const AES_EXTRA_FIELD_ID: u16 = 0x9901;
+const AES_EXTRA_SIZE: u32 = 11;
+/// AE-1 stores the CRC-32, AE-2 leaves it out and relies on the
+/// authentication code
+pub const AE_1: u16 = 1;
+pub const AE_2: u16 = 2;
+const AES_VENDOR: &[u8; 2] = b"AE";
+pub const AES_256: u8 = 3;
+const TIMESTAMP_MTIME: u8 = 0b01;
+const TIMESTAMP_ATIME: u8 = 0b10;
 
 const MIN_VERSION: u16 = 20;
-const FLAGS: u16 = 0b0000_1000_0000_1000;
-const COMPRESS_STORE: u16 = 0;
+const ZIP64_VERSION: u16 = 45;
+const BZIP2_VERSION: u16 = 46;
+const LZMA_VERSION: u16 = 63;
+const AES_VERSION: u16 = 51;
+/// Entry data is encrypted
+const FLAG_ENCRYPTED: u16 = 1;
+/// LZMA data ends with an end of stream marker
+const FLAG_LZMA_EOS: u16 = 1 << 1;
+/// Sizes and CRC-32 follow the data in a data descriptor
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+/// File name is UTF-8, legacy names are CP437
+pub const FLAG_UTF8: u16 = 1 << 11;
+/// Upper byte of "version made by", the external attributes hold Unix modes
+pub const HOST_UNIX: u16 = 3 << 8;
+/// MS-DOS directory attribute, in the low byte of the external attributes
+const DOS_DIRECTORY: u32 = 0x10;
+
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFLNK: u32 = 0o120000;
+
+pub const COMPRESS_STORE: u16 = 0;
+pub const COMPRESS_DEFLATE: u16 = 8;
+pub const COMPRESS_BZIP2: u16 = 12;
+pub const COMPRESS_LZMA: u16 = 14;
+pub const COMPRESS_ZSTD: u16 = 93;
+pub const COMPRESS_AES: u16 = 99;
+
+/// Compression method of an entry, levels are 0 - 9.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Store,
+    Deflate { level: u32 },
+    Zstd { level: u32 },
+    Bzip2 { level: u32 },
+    Lzma { level: u32 },
+}
+
+impl Compression {
+    pub fn method(&self) -> u16 {
+        match self {
+            Compression::Store => COMPRESS_STORE,
+            Compression::Deflate { .. } => COMPRESS_DEFLATE,
+            Compression::Zstd { .. } => COMPRESS_ZSTD,
+            Compression::Bzip2 { .. } => COMPRESS_BZIP2,
+            Compression::Lzma { .. } => COMPRESS_LZMA,
+        }
+    }
+
+    /// Version needed to extract the method.
+    fn version(&self) -> u16 {
+        match self {
+            Compression::Store | Compression::Deflate { .. } => MIN_VERSION,
+            Compression::Bzip2 { .. } => BZIP2_VERSION,
+            Compression::Zstd { .. } | Compression::Lzma { .. } => LZMA_VERSION,
+        }
+    }
+
+    /// Upper bound of the compressed size of `size` bytes, used to decide
+    /// up front whether the entry needs ZIP64 records.
+    fn max_compressed_size(&self, size: u64) -> u64 {
+        match self {
+            Compression::Store => size,
+            // zlib deflateBound
+            Compression::Deflate { .. } => size + (size >> 12) + (size >> 14) + (size >> 25) + 13,
+            // generous bound of the block overhead of the others
+            _ => size + (size >> 6) + 64 * 1024,
+        }
+    }
+}
+
+/// LZMA SDK version written before the LZMA properties, informational only
+const LZMA_SDK_VERSION: [u8; 2] = [5, 2];
+pub const LZMA_PROPERTIES_SIZE: u16 = 5;
+/// Header of the .lzma format: properties, dictionary size and uncompressed
+/// size
+pub const LZMA_ALONE_HEADER_SIZE: usize = 13;
+
+/// Output of the .lzma encoder, with the ZIP LZMA header in place of the
+/// .lzma header: SDK version, properties size, properties and dictionary
+/// size.
+#[derive(Default)]
+pub struct LzmaOutput {
+    header: Vec<u8>,
+    output: Vec<u8>,
+}
+
+impl Write for LzmaOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.header.len() < LZMA_ALONE_HEADER_SIZE {
+            let take = buf.len().min(LZMA_ALONE_HEADER_SIZE - self.header.len());
+            self.header.extend_from_slice(&buf[..take]);
+            if self.header.len() == LZMA_ALONE_HEADER_SIZE {
+                self.output.extend_from_slice(&LZMA_SDK_VERSION);
+                self.output
+                    .extend_from_slice(&LZMA_PROPERTIES_SIZE.to_le_bytes());
+                // the uncompressed size is left out, the data ends with a marker
+                self.output
+                    .extend_from_slice(&self.header[..LZMA_PROPERTIES_SIZE as usize]);
+            }
+            return Ok(take);
+        }
+        self.output.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
 
-pub fn calc_size<P, I>(sizes: I) -> Result<u64>
+/// Streaming compressor for one entry, fed chunk by chunk.
+pub enum Compressor {
+    Store,
+    Deflate(DeflateEncoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+    Bzip2(BzEncoder<Vec<u8>>),
+    Lzma(XzEncoder<LzmaOutput>),
+}
+
+impl Compressor {
+    pub fn new(compression: Compression) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::Store => Compressor::Store,
+            Compression::Deflate { level } => Compressor::Deflate(DeflateEncoder::new(
+                Vec::new(),
+                flate2::Compression::new(level),
+            )),
+            // zstd levels go up to 22, 0 is its default one
+            Compression::Zstd { level } => {
+                Compressor::Zstd(zstd::stream::write::Encoder::new(Vec::new(), level as i32)?)
+            }
+            // bzip2 has no level 0, the CLI refuses it
+            Compression::Bzip2 { level } => Compressor::Bzip2(BzEncoder::new(
+                Vec::new(),
+                bzip2::Compression::new(level.clamp(1, 9)),
+            )),
+            Compression::Lzma { level } => {
+                let options = LzmaOptions::new_preset(level.min(9))?;
+                let stream = Stream::new_lzma_encoder(&options)?;
+                Compressor::Lzma(XzEncoder::new_stream(LzmaOutput::default(), stream))
+            }
+        })
+    }
+
+    /// Compresses `data`, returning the output that is ready so far.
+    pub fn compress(&mut self, data: Vec<u8>) -> io::Result<Vec<u8>> {
+        match self {
+            Compressor::Store => Ok(data),
+            Compressor::Deflate(encoder) => {
+                encoder.write_all(&data)?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Compressor::Zstd(encoder) => {
+                encoder.write_all(&data)?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Compressor::Bzip2(encoder) => {
+                encoder.write_all(&data)?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Compressor::Lzma(encoder) => {
+                encoder.write_all(&data)?;
+                Ok(std::mem::take(&mut encoder.get_mut().output))
+            }
+        }
+    }
+
+    /// Flushes the remaining compressed output.
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Compressor::Store => Ok(Vec::new()),
+            Compressor::Deflate(encoder) => encoder.finish(),
+            Compressor::Zstd(encoder) => encoder.finish(),
+            Compressor::Bzip2(encoder) => encoder.finish(),
+            Compressor::Lzma(encoder) => Ok(encoder.finish()?.output),
+        }
+    }
+}
+
+/// Predicts the exact size of the archive `Zipper` produces for the given
+/// entry names and sizes, including any ZIP64 records needed for large
+/// files or archives. Only stored entries can be predicted.
+#[cfg(test)]
+pub fn calc_size<S, I>(sizes: I) -> Result<u64>
 where
-    I: IntoIterator<Item = (P, u64)>,
-    P: AsRef<Path>,
+    I: IntoIterator<Item = (S, u64)>,
+    S: AsRef<str>,
 {
-    sizes
+    let mut pos: u64 = 0;
+    let mut dir_size: u64 = 0;
+    let mut number_of_files: u64 = 0;
+
+    for (name, sz) in sizes {
+        let name_len = name.as_ref().len() as u64;
+        let zip64 = needs_zip64(sz);
+        let offset = pos;
+
+        let unicode_len = unicode_path_extra_len(name.as_ref()) as u64;
+        pos += (FILE_HEADER_SIZE + TIMESTAMP_LOCAL_EXTRA_SIZE) as u64 + unicode_len + name_len + sz;
+        if zip64 {
+            pos += (ZIP64_LOCAL_EXTRA_SIZE + ZIP64_DATA_DESCRIPTOR_SIZE) as u64;
+        } else {
+            pos += DATA_DESCRIPTOR_SIZE as u64;
+        }
+
+        dir_size += (DIRECTORY_ENTRY_SIZE + TIMESTAMP_CENTRAL_EXTRA_SIZE) as u64
+            + unicode_len
+            + name_len
+            + zip64_extra_len(sz, sz, offset) as u64;
+        number_of_files += 1;
+    }
+
+    let end = DirectoryEnd {
+        disk: 0,
+        end_offset: pos + dir_size,
+        dir_disk: 0,
+        files_on_disk: number_of_files,
+        number_of_files,
+        dir_size,
+        dir_offset: pos,
+        comment: "",
+    };
+
+    Ok(pos + dir_size + end.size() as u64)
+}
+
+/// Whether a size or offset has to be stored in a ZIP64 extra field
+/// because it does not fit the 32-bit field (0xFFFFFFFF is the marker).
+fn needs_zip64(value: u64) -> bool {
+    value >= std::u32::MAX as u64
+}
+
+/// Length of the ZIP64 extended information extra field of a central
+/// directory entry, 0 if none of the values overflow.
+fn zip64_extra_len(uncompressed: u64, compressed: u64, offset: u64) -> u16 {
+    let fields = [uncompressed, compressed, offset]
         .into_iter()
-        .try_fold(DIRECTORY_END_SIZE as u64, |total, (path, sz)| {
-            Ok(total
-                + FILE_HEADER_SIZE as u64
-                + 2 * path_to_file_name(&path)?.len() as u64
-                + sz
-                + DATA_DESCRIPTOR_SIZE as u64
-                + DIRECTORY_ENTRY_SIZE as u64)
-        })
+        .filter(|v| needs_zip64(*v))
+        .count() as u16;
+    if fields == 0 {
+        0
+    } else {
+        4 + 8 * fields
+    }
+}
+
+/// Unix mode of a file, with its file type bits. Normalized modes only keep
+/// whether the file is executable, for reproducible archives.
+pub fn unix_mode(meta: &std::fs::Metadata, normalize: bool) -> Option<u32> {
+    #[cfg(unix)]
+    let mode = {
+        use std::os::unix::fs::MetadataExt;
+        meta.mode()
+    };
+    #[cfg(not(unix))]
+    let mode = if normalize {
+        if meta.is_dir() {
+            S_IFDIR | 0o755
+        } else {
+            S_IFREG | 0o644
+        }
+    } else {
+        return None;
+    };
+
+    if !normalize {
+        return Some(mode);
+    }
+    Some(match mode & S_IFMT {
+        S_IFDIR => S_IFDIR | 0o755,
+        S_IFLNK => S_IFLNK | 0o777,
+        _ if mode & 0o111 != 0 => S_IFREG | 0o755,
+        _ => S_IFREG | 0o644,
+    })
 }
 
-fn path_to_file_name<P: AsRef<Path>>(path: &P) -> Result<Cow<'_, str>> {
-    Ok(path
-        .as_ref()
-        .file_name()
-        .ok_or(Error::InvalidPath)?
-        .to_string_lossy())
+#[cfg(test)]
+pub fn path_to_file_name<P: AsRef<Path>>(path: &P, names: NonUtf8Names) -> Result<Cow<'_, str>> {
+    let name = path.as_ref().file_name().ok_or(Error::InvalidPath)?;
+    names
+        .convert(name)
+        .ok_or_else(|| Error::Encoding(path.as_ref().to_owned()))
 }
+
+/// Length of the Unicode path extra field of `name`, only names that are not
+/// ASCII have one.
+fn unicode_path_extra_len(name: &str) -> u32 {
+    if name.is_ascii() {
+        0
+    } else {
+        4 + 5 + name.len() as u32
+    }
+}
+
+fn put_unicode_path<T: BufMut>(buf: &mut T, name: &str) {
+    if name.is_ascii() {
+        return;
+    }
+    buf.put_u16_le(UNICODE_PATH_ID);
+    buf.put_u16_le((unicode_path_extra_len(name) - 4) as u16);
+    buf.put_u8(UNICODE_PATH_VERSION);
+    // CRC-32 of the name field, the field is stale if they differ
+    buf.put_u32_le(crc32fast::hash(name.as_bytes()));
+    buf.put_slice(name.as_bytes());
+}
+
+fn comment_len(comment: &str) -> Result<u16> {
+    u16::try_from(comment.len()).map_err(|_| Error::CommentTooBig)
+}
+
+/// Fails on extra fields over the 16-bit length field.
+fn check_extra_len(extra_len: u32) -> Result<u16> {
+    u16::try_from(extra_len).map_err(|_| Error::FileNameTooBig)
+}
+
 pub trait ToBytes {
     fn to_bytes(&self) -> Result<Vec<u8>>;
 }
@@ -50,69 +382,221 @@ pub trait ToBytes {
 pub struct FileHeader {
     file_name: String,
     modified: Timestamp,
+    compression: Compression,
+    zip64: bool,
+    /// Unix mode, with its file type bits
+    mode: Option<u32>,
+    /// Entry comment, in the central directory only
+    comment: String,
+    max_compressed_size: u64,
+    encrypted: bool,
 }
 
 impl FileHeader {
-    pub fn new(path: impl AsRef<Path>, modified: impl Into<Timestamp>) -> Result<Self> {
-        let file_name = path_to_file_name(&path)?.to_string();
+    /// `size` is the expected size of the file, it decides whether the
+    /// entry is written with ZIP64 local header and data descriptor.
+    pub fn new(
+        file_name: impl Into<String>,
+        modified: impl Into<Timestamp>,
+        size: u64,
+        compression: Compression,
+    ) -> Result<Self> {
+        let max_compressed_size = compression.max_compressed_size(size);
         Ok(FileHeader {
-            file_name,
+            file_name: file_name.into(),
             modified: modified.into(),
+            compression,
+            zip64: needs_zip64(max_compressed_size),
+            mode: None,
+            comment: String::new(),
+            max_compressed_size,
+            encrypted: false,
         })
     }
+
+    /// Marks the entry as encrypted with WinZip AES-256, its data starts with
+    /// the salt and password verifier and ends with the authentication code.
+    pub fn encrypted(mut self, encrypted: bool) -> Self {
+        self.encrypted = encrypted;
+        if encrypted {
+            self.zip64 = needs_zip64(self.max_compressed_size + aes::OVERHEAD);
+        }
+        self
+    }
+
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = comment.into();
+        self
+    }
+
+    /// Records the Unix `mode` in the central directory.
+    pub fn mode(mut self, mode: Option<u32>) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn zip64(&self) -> bool {
+        self.zip64
+    }
+
+    fn flags(&self) -> u16 {
+        let mut flags = FLAG_DATA_DESCRIPTOR;
+        if !self.file_name.is_ascii() || !self.comment.is_ascii() {
+            flags |= FLAG_UTF8;
+        }
+        if self.encrypted {
+            flags |= FLAG_ENCRYPTED;
+        }
+        if let Compression::Lzma { .. } = self.compression {
+            flags |= FLAG_LZMA_EOS;
+        }
+        flags
+    }
+
+    fn version(&self) -> u16 {
+        let version = if self.encrypted {
+            AES_VERSION
+        } else if self.zip64 {
+            ZIP64_VERSION
+        } else {
+            MIN_VERSION
+        };
+        version.max(self.compression.version())
+    }
+
+    /// Compression method of the headers, the actual one of encrypted
+    /// entries is in the AES extra field.
+    fn method(&self) -> u16 {
+        if self.encrypted {
+            COMPRESS_AES
+        } else {
+            self.compression.method()
+        }
+    }
+
+    fn aes_extra_len(&self) -> u32 {
+        if self.encrypted {
+            AES_EXTRA_SIZE
+        } else {
+            0
+        }
+    }
+
+    fn put_aes_extra<T: BufMut>(&self, buf: &mut T) {
+        if !self.encrypted {
+            return;
+        }
+        buf.put_u16_le(AES_EXTRA_FIELD_ID);
+        buf.put_u16_le((AES_EXTRA_SIZE - 4) as u16);
+        buf.put_u16_le(AE_2);
+        buf.put_slice(AES_VENDOR);
+        buf.put_u8(AES_256);
+        buf.put_u16_le(self.compression.method());
+    }
 }
 
 impl ToBytes for FileHeader {
     fn to_bytes(&self) -> Result<Vec<u8>> {
-        let mut h = BytesMut::with_capacity(FILE_HEADER_SIZE as usize + self.file_name.len());
+        let zip64_len = if self.zip64 {
+            ZIP64_LOCAL_EXTRA_SIZE
+        } else {
+            0
+        };
+        let extra_len = zip64_len
+            + TIMESTAMP_LOCAL_EXTRA_SIZE
+            + unicode_path_extra_len(&self.file_name)
+            + self.aes_extra_len();
+        let mut h =
+            BytesMut::with_capacity((FILE_HEADER_SIZE + extra_len) as usize + self.file_name.len());
 
         // local file header signature
         h.put_u32_le(LOCAL_FILE_HEADER_SIGNATURE);
         // version needed to extract
-        h.put_u16_le(MIN_VERSION);
+        h.put_u16_le(self.version());
         // general purpose bit flag
-        h.put_u16_le(FLAGS);
+        h.put_u16_le(self.flags());
         // Compression method
-        h.put_u16_le(COMPRESS_STORE);
+        h.put_u16_le(self.method());
         // last mod file time and last mod file date
         h.put_u16_le(self.modified.dos_timepart());
-        h.put_u16_le(self.modified.dos_datepart()?);
+        h.put_u16_le(self.modified.dos_datepart());
         // crc-32
         h.put_u32_le(0);
-        // compressed size
-        h.put_u32_le(0);
-        // uncompressed size
-        h.put_u32_le(0);
+        // compressed size and uncompressed size, deferred to the data
+        // descriptor, ZIP64 entries mark them as stored in the extra field
+        let size = if self.zip64 { std::u32::MAX } else { 0 };
+        h.put_u32_le(size);
+        h.put_u32_le(size);
         // file name length
         if self.file_name.len() > std::u16::MAX as usize {
             return Err(Error::FileNameTooBig);
         }
         h.put_u16_le(self.file_name.as_bytes().len() as u16);
         // extra field length
-        h.put_u16_le(0);
+        h.put_u16_le(check_extra_len(extra_len)?);
         // file name
         h.put_slice(self.file_name.as_bytes());
+        // ZIP64 extended information, sizes follow in the data descriptor
+        if self.zip64 {
+            h.put_u16_le(ZIP64_EXTRA_FIELD_ID);
+            h.put_u16_le(16);
+            h.put_u64_le(0);
+            h.put_u64_le(0);
+        }
+        // extended timestamp
+        h.put_u16_le(EXTENDED_TIMESTAMP_ID);
+        h.put_u16_le((TIMESTAMP_LOCAL_EXTRA_SIZE - 4) as u16);
+        h.put_u8(TIMESTAMP_MTIME | TIMESTAMP_ATIME);
+        h.put_i32_le(self.modified.mtime());
+        h.put_i32_le(self.modified.atime());
+        put_unicode_path(&mut h, &self.file_name);
+        self.put_aes_extra(&mut h);
 
         Ok(h.to_vec())
     }
 }
 
 pub struct Descriptor {
+    compressed_size: u64,
     size: u64,
     crc: u32,
+    zip64: bool,
 }
 
 impl Descriptor {
-    pub fn new(size: u64, crc: u32) -> Self {
-        Descriptor { size, crc }
+    /// `zip64` must match the local header of the entry, readers size the
+    /// descriptor after it.
+    pub fn new(compressed_size: u64, size: u64, crc: u32, zip64: bool) -> Self {
+        Descriptor {
+            compressed_size,
+            size,
+            crc,
+            zip64,
+        }
     }
 }
 
 impl ToBytes for Descriptor {
     fn to_bytes(&self) -> Result<Vec<u8>> {
+        if self.zip64 {
+            let mut d = BytesMut::with_capacity(ZIP64_DATA_DESCRIPTOR_SIZE as usize);
+
+            // data_descriptor header signature
+            d.put_u32_le(DATA_DESCRIPTOR_SIGNATURE);
+            // crc-32
+            d.put_u32_le(self.crc);
+            // compressed size
+            d.put_u64_le(self.compressed_size);
+            // uncompressed size
+            d.put_u64_le(self.size);
+
+            return Ok(d.to_vec());
+        }
+
         let mut d = BytesMut::with_capacity(DATA_DESCRIPTOR_SIZE as usize);
 
-        if self.size > std::u32::MAX as u64 {
+        // the file grew past 4GB after its header was written
+        if needs_zip64(self.size) || needs_zip64(self.compressed_size) {
             return Err(Error::FileTooBig(self.size));
         }
 
@@ -121,7 +605,7 @@ impl ToBytes for Descriptor {
         // crc-32
         d.put_u32_le(self.crc);
         // compressed size
-        d.put_u32_le(self.size as u32);
+        d.put_u32_le(self.compressed_size as u32);
         // uncompressed size
         d.put_u32_le(self.size as u32);
 
@@ -132,155 +616,406 @@ impl ToBytes for Descriptor {
 pub struct DirectoryEntry {
     header: FileHeader,
     desc: Descriptor,
+    /// Volume of the local file header and its offset in the volume
+    disk: u32,
     offset: u64,
 }
 
 impl DirectoryEntry {
     fn size(&self) -> u32 {
-        (self.header.file_name.len() + DIRECTORY_END_SIZE as usize) as u32
+        DIRECTORY_ENTRY_SIZE
+            + self.header.file_name.len() as u32
+            + self.extra_len()
+            + self.header.comment.len() as u32
+    }
+
+    fn extra_len(&self) -> u32 {
+        self.zip64_extra_len() as u32
+            + TIMESTAMP_CENTRAL_EXTRA_SIZE
+            + unicode_path_extra_len(&self.header.file_name)
+            + self.header.aes_extra_len()
+    }
+
+    fn zip64_extra_len(&self) -> u16 {
+        zip64_extra_len(self.desc.size, self.desc.compressed_size, self.offset)
     }
 }
 
 impl DirectoryEntry {
     fn add_to_bytes<T: BufMut>(&self, buf: &mut T) -> Result<()> {
+        let zip64_len = self.zip64_extra_len();
+        let version = if zip64_len > 0 {
+            self.header.version().max(ZIP64_VERSION)
+        } else {
+            self.header.version()
+        };
+
+        let (made_by, external_attributes) = match self.header.mode {
+            Some(mode) => {
+                let dos = if self.header.file_name.ends_with('/') {
+                    DOS_DIRECTORY
+                } else {
+                    0
+                };
+                (HOST_UNIX | version, mode << 16 | dos)
+            }
+            None => (version, 0),
+        };
+
         // central file header signature
         buf.put_u32_le(CENTRAL_DIRECTORY_HEADER_SIGNATURE);
         // version made by
-        buf.put_u16_le(MIN_VERSION);
+        buf.put_u16_le(made_by);
         // version needed to extract
-        buf.put_u16_le(MIN_VERSION);
+        buf.put_u16_le(version);
         // general puprose bit flag
-        buf.put_u16_le(FLAGS);
+        buf.put_u16_le(self.header.flags());
         // compression method
-        buf.put_u16_le(COMPRESS_STORE);
+        buf.put_u16_le(self.header.method());
         // last mod file time + date
         buf.put_u16_le(self.header.modified.dos_timepart());
-        buf.put_u16_le(self.header.modified.dos_datepart()?);
+        buf.put_u16_le(self.header.modified.dos_datepart());
         // crc-32
         buf.put_u32_le(self.desc.crc);
         // compressed size
-        if self.desc.size > std::u32::MAX as u64 {
-            return Err(Error::FileTooBig(self.desc.size));
-        }
-        buf.put_u32_le(self.desc.size as u32);
+        buf.put_u32_le(self.desc.compressed_size.min(std::u32::MAX as u64) as u32);
         // uncompressed size
-        buf.put_u32_le(self.desc.size as u32);
+        buf.put_u32_le(self.desc.size.min(std::u32::MAX as u64) as u32);
         // file name length
         if self.header.file_name.len() > std::u16::MAX as usize {
             return Err(Error::FileNameTooBig);
         }
         buf.put_u16_le(self.header.file_name.as_bytes().len() as u16);
         // extra field length
-        buf.put_u16_le(0);
+        buf.put_u16_le(check_extra_len(self.extra_len())?);
         // file comment length
-        buf.put_u16_le(0);
+        buf.put_u16_le(comment_len(&self.header.comment)?);
         // disk number start
-        buf.put_u16_le(0);
+        buf.put_u16_le(self.disk as u16);
         // internal file attributes
         buf.put_u16_le(0);
         // external file attributes
-        buf.put_u32_le(0);
+        buf.put_u32_le(external_attributes);
         // relative offset of local header
-        if self.offset > std::u32::MAX as u64 {
-            return Err(Error::ArchiveTooBig);
-        }
-        buf.put_u32_le(self.offset as u32);
+        buf.put_u32_le(self.offset.min(std::u32::MAX as u64) as u32);
         // file name
         buf.put_slice(self.header.file_name.as_bytes());
-        // extra field
+        // extra field, ZIP64 extended information holds the values marked
+        // as 0xFFFFFFFF above, in this order
+        if zip64_len > 0 {
+            buf.put_u16_le(ZIP64_EXTRA_FIELD_ID);
+            buf.put_u16_le(zip64_len - 4);
+            for value in [self.desc.size, self.desc.compressed_size, self.offset] {
+                if needs_zip64(value) {
+                    buf.put_u64_le(value);
+                }
+            }
+        }
+        // extended timestamp, the central one only has the modification time
+        buf.put_u16_le(EXTENDED_TIMESTAMP_ID);
+        buf.put_u16_le((TIMESTAMP_CENTRAL_EXTRA_SIZE - 4) as u16);
+        buf.put_u8(TIMESTAMP_MTIME | TIMESTAMP_ATIME);
+        buf.put_i32_le(self.header.modified.mtime());
+        put_unicode_path(buf, &self.header.file_name);
+        self.header.put_aes_extra(buf);
         // file comment
-        // <none>
+        buf.put_slice(self.header.comment.as_bytes());
 
         Ok(())
     }
 }
 
-struct DirectoryEnd {
-    number_of_files: u16,
-    dir_size: u32,
+struct DirectoryEnd<'a> {
+    /// Volume of the end records, the last one
+    disk: u32,
+    /// Offset of the end records in their volume
+    end_offset: u64,
+    /// Volume where the central directory starts
+    dir_disk: u32,
+    /// Central directory entries in the last volume
+    files_on_disk: u64,
+    number_of_files: u64,
+    dir_size: u64,
     dir_offset: u64,
+    comment: &'a str,
 }
 
-impl DirectoryEnd {
+impl DirectoryEnd<'_> {
+    fn zip64(&self) -> bool {
+        self.number_of_files >= std::u16::MAX as u64
+            || needs_zip64(self.dir_size)
+            || needs_zip64(self.dir_offset)
+    }
+
+    fn size(&self) -> u32 {
+        let size = if self.zip64() {
+            ZIP64_DIRECTORY_END_SIZE + ZIP64_DIRECTORY_END_LOCATOR_SIZE + DIRECTORY_END_SIZE
+        } else {
+            DIRECTORY_END_SIZE
+        };
+        size + self.comment.len() as u32
+    }
+
     fn add_to_bytes<T: BufMut>(&self, buf: &mut T) -> Result<()> {
+        if self.zip64() {
+            self.add_zip64_to_bytes(buf);
+        }
+
         // signature
         buf.put_u32_le(CENTRAL_DIRECTORY_END_SIGNATURE);
         // disk number
-        buf.put_u16_le(0);
+        buf.put_u16_le(self.disk as u16);
         // disk with central directory
-        buf.put_u16_le(0);
+        buf.put_u16_le(self.dir_disk as u16);
         //number of files on this disk
-        buf.put_u16_le(self.number_of_files);
+        buf.put_u16_le(self.files_on_disk.min(std::u16::MAX as u64) as u16);
         // total number of files
-        buf.put_u16_le(self.number_of_files);
+        buf.put_u16_le(self.number_of_files.min(std::u16::MAX as u64) as u16);
         // directory size
-        buf.put_u32_le(self.dir_size);
+        buf.put_u32_le(self.dir_size.min(std::u32::MAX as u64) as u32);
         // directory offset from start
-        if self.dir_offset > std::u32::MAX as u64 {
-            return Err(Error::ArchiveTooBig);
-        }
-        buf.put_u32_le(self.dir_offset as u32);
+        buf.put_u32_le(self.dir_offset.min(std::u32::MAX as u64) as u32);
         // Comment length
-        buf.put_u16_le(0);
+        buf.put_u16_le(comment_len(self.comment)?);
         // Comment
-        //buf.put_all(&self.zip_file_comment);
+        buf.put_slice(self.comment.as_bytes());
 
         Ok(())
     }
+
+    /// ZIP64 end of central directory record and its locator, they hold
+    /// the values marked as 0xFFFF / 0xFFFFFFFF in the regular record.
+    fn add_zip64_to_bytes<T: BufMut>(&self, buf: &mut T) {
+        // signature
+        buf.put_u32_le(ZIP64_CENTRAL_DIRECTORY_END_SIGNATURE);
+        // size of the remaining record
+        buf.put_u64_le((ZIP64_DIRECTORY_END_SIZE - 12) as u64);
+        // version made by
+        buf.put_u16_le(ZIP64_VERSION);
+        // version needed to extract
+        buf.put_u16_le(ZIP64_VERSION);
+        // disk number
+        buf.put_u32_le(self.disk);
+        // disk with central directory
+        buf.put_u32_le(self.dir_disk);
+        // number of files on this disk
+        buf.put_u64_le(self.files_on_disk);
+        // total number of files
+        buf.put_u64_le(self.number_of_files);
+        // directory size
+        buf.put_u64_le(self.dir_size);
+        // directory offset from start
+        buf.put_u64_le(self.dir_offset);
+
+        // locator signature
+        buf.put_u32_le(ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIGNATURE);
+        // disk with the zip64 end of central directory
+        buf.put_u32_le(self.disk);
+        // zip64 end of central directory offset
+        buf.put_u64_le(self.end_offset);
+        // total number of disks
+        buf.put_u32_le(self.disk + 1);
+    }
+}
+
+/// Piece of an archive stream.
+#[derive(Debug)]
+pub enum SplitChunk {
+    Data(Vec<u8>),
+    /// The volume is complete, the data that follows starts the next one
+    NextVolume,
+}
+
+/// Layout of an archive in volumes of at most `split_size` bytes, records
+/// (headers, descriptors and the central directory ones) are not split
+/// across volumes.
+pub struct Volumes {
+    split_size: Option<u64>,
+    disk: u32,
+    /// Offset in the current volume
+    offset: u64,
+}
+
+impl Volumes {
+    pub fn new(split_size: Option<u64>) -> Self {
+        Volumes {
+            split_size,
+            disk: 0,
+            offset: 0,
+        }
+    }
+
+    /// Volume and offset in it of the next byte.
+    pub fn position(&self) -> (u32, u64) {
+        (self.disk, self.offset)
+    }
+
+    /// Starts a new volume unless a record of `len` bytes fits the current
+    /// one, records bigger than a volume cannot help being split.
+    pub fn reserve(&mut self, len: u64) -> Result<Option<SplitChunk>> {
+        match self.split_size {
+            Some(size) if self.offset > 0 && self.offset + len > size && len <= size => {
+                self.next_volume().map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Lays out entry data, split where volumes are full.
+    pub fn data(&mut self, mut data: Vec<u8>) -> Result<Vec<SplitChunk>> {
+        let mut chunks = vec![];
+        if let Some(size) = self.split_size {
+            while self.offset + data.len() as u64 > size {
+                let rest = data.split_off((size - self.offset) as usize);
+                if !data.is_empty() {
+                    chunks.push(SplitChunk::Data(data));
+                }
+                chunks.push(self.next_volume()?);
+                data = rest;
+            }
+        }
+        if !data.is_empty() {
+            self.offset += data.len() as u64;
+            chunks.push(SplitChunk::Data(data));
+        }
+        Ok(chunks)
+    }
+
+    fn next_volume(&mut self) -> Result<SplitChunk> {
+        // disk numbers are 16-bit outside of ZIP64 records
+        if self.disk + 1 >= std::u16::MAX as u32 {
+            return Err(Error::TooManyVolumes);
+        }
+        self.disk += 1;
+        self.offset = 0;
+        Ok(SplitChunk::NextVolume)
+    }
 }
 
 pub struct Directory {
     entries: Vec<DirectoryEntry>,
-    offset: Option<u64>,
+    comment: String,
 }
 
 impl Directory {
     pub fn new() -> Self {
         Directory {
             entries: Vec::new(),
-            offset: None,
+            comment: String::new(),
         }
     }
 
-    pub fn add_entry(&mut self, header: FileHeader, desc: Descriptor, offset: u64) {
+    /// Sets the archive comment.
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = comment.into();
+        self
+    }
+
+    /// Adds the entry whose local header is at `offset` in the volume `disk`.
+    pub fn add_entry(&mut self, header: FileHeader, desc: Descriptor, (disk, offset): (u32, u64)) {
         self.entries.push(DirectoryEntry {
             header,
             desc,
+            disk,
             offset,
         })
     }
 
-    pub fn finalize(mut self, offset: u64) -> Result<Vec<u8>> {
-        self.offset = Some(offset);
-        self.to_bytes()
-    }
-}
-
-impl ToBytes for Directory {
-    fn to_bytes(&self) -> Result<Vec<u8>> {
-        let num_files = self.entries.len();
-        let cap = self.entries.iter().map(|e| e.size()).sum::<u32>() + DIRECTORY_END_SIZE;
-        let mut d = BytesMut::with_capacity(cap as usize);
+    /// Lays out the central directory and the end records after the entries.
+    pub fn finalize(self, volumes: &mut Volumes) -> Result<Vec<SplitChunk>> {
+        let mut chunks = vec![];
+        let mut dir_start = None;
+        let mut dir_size: u64 = 0;
+        let mut disks = Vec::with_capacity(self.entries.len());
         for e in &self.entries {
+            let mut d = BytesMut::with_capacity(e.size() as usize);
             e.add_to_bytes(&mut d)?;
+            chunks.extend(volumes.reserve(d.len() as u64)?);
+            let position = volumes.position();
+            dir_start.get_or_insert(position);
+            disks.push(position.0);
+            dir_size += d.len() as u64;
+            chunks.extend(volumes.data(d.to_vec())?);
         }
 
-        let dir_size = d.len();
-
-        let offset = self
-            .offset
-            .expect("invalid state - must update offset first");
-        if offset > std::u32::MAX as u64 {
-            return Err(Error::ArchiveTooBig);
-        }
-        let end = DirectoryEnd {
-            dir_offset: offset,
-            dir_size: dir_size as u32,
-            number_of_files: num_files as u16,
+        let (dir_disk, dir_offset) = dir_start.unwrap_or_else(|| volumes.position());
+        let mut end = DirectoryEnd {
+            disk: 0,
+            end_offset: 0,
+            dir_disk,
+            files_on_disk: 0,
+            number_of_files: self.entries.len() as u64,
+            dir_size,
+            dir_offset,
+            comment: &self.comment,
         };
-
+        // the end records stay together in the last volume
+        chunks.extend(volumes.reserve(end.size() as u64)?);
+        (end.disk, end.end_offset) = volumes.position();
+        end.files_on_disk = disks.iter().filter(|&&disk| disk == end.disk).count() as u64;
+        let mut d = BytesMut::with_capacity(end.size() as usize);
         end.add_to_bytes(&mut d)?;
+        chunks.extend(volumes.data(d.to_vec())?);
 
-        Ok(d.to_vec())
+        Ok(chunks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+
+    #[test]
+    fn test_zip64_entry() -> Result<()> {
+        let size: u64 = 5 * 1024 * 1024 * 1024;
+        let header = FileHeader::new("big.bin", SystemTime::now(), size, Compression::Store)?;
+        assert!(header.zip64());
+        let header_bytes = header.to_bytes()?;
+        assert_eq!(
+            header_bytes.len(),
+            (FILE_HEADER_SIZE + ZIP64_LOCAL_EXTRA_SIZE + TIMESTAMP_LOCAL_EXTRA_SIZE) as usize
+                + "big.bin".len()
+        );
+
+        let desc = Descriptor::new(size, size, 0, header.zip64());
+        let desc_bytes = desc.to_bytes()?;
+        assert_eq!(desc_bytes.len(), ZIP64_DATA_DESCRIPTOR_SIZE as usize);
+
+        let dir_offset = header_bytes.len() as u64 + size + desc_bytes.len() as u64;
+        let mut dir = Directory::new();
+        dir.add_entry(header, desc, (0, 0));
+        let mut volumes = Volumes {
+            split_size: None,
+            disk: 0,
+            offset: dir_offset,
+        };
+        let dir_bytes = dir
+            .finalize(&mut volumes)?
+            .into_iter()
+            .flat_map(|chunk| match chunk {
+                SplitChunk::Data(data) => data,
+                SplitChunk::NextVolume => unreachable!("the archive is not split"),
+            })
+            .collect::<Vec<_>>();
+
+        // both sizes go to the extra field, the offset still fits
+        let entry_size = (DIRECTORY_ENTRY_SIZE + 4 + 16 + TIMESTAMP_CENTRAL_EXTRA_SIZE) as usize
+            + "big.bin".len();
+        assert_eq!(
+            &dir_bytes[entry_size..entry_size + 4],
+            &ZIP64_CENTRAL_DIRECTORY_END_SIGNATURE.to_le_bytes()
+        );
+        // the regular end record points to the zip64 one
+        let end = &dir_bytes[dir_bytes.len() - DIRECTORY_END_SIZE as usize..];
+        assert_eq!(&end[16..20], &std::u32::MAX.to_le_bytes());
+
+        assert_eq!(
+            calc_size([("big.bin", size)])?,
+            dir_offset + dir_bytes.len() as u64
+        );
+
+        Ok(())
     }
 }
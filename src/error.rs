@@ -0,0 +1,60 @@
+use std::{io, path::PathBuf};
+
+use thiserror::Error;
+
+use crate::async_zip;
+
+/// Errors of zipping a directory, on top of the archive format errors.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Archive(#[from] async_zip::error::Error),
+    #[error("Cannot walk {path:?}: {source}")]
+    Walk { path: PathBuf, source: io::Error },
+    #[error("Cannot write {path:?}: {source}")]
+    Write { path: PathBuf, source: io::Error },
+    #[error("File name {0:?} is not valid UTF-8")]
+    Encoding(PathBuf),
+    #[error("Symlink {0:?} cannot be stored by this zip type")]
+    UnsupportedSymlink(PathBuf),
+    #[error("Compression {0:?} is not supported by this zip type")]
+    UnsupportedCompression(async_zip::Compression),
+    #[error("Entry stream is broken: {0}")]
+    EntryStream(&'static str),
+    #[error("Invalid name template {0}")]
+    Template(String),
+    #[error("Invalid archive name {0}")]
+    InvalidName(String),
+    #[error("Archive {0:?} is also the archive of another directory")]
+    DuplicateArchive(PathBuf),
+    #[error("Zip error {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("Async zip error {0}")]
+    AsyncZip(#[from] ::async_zip::error::ZipError),
+    #[error("Zip task failed {0}")]
+    Join(#[from] tokio::task::JoinError),
+    #[error("IO error {0}")]
+    Io(#[from] io::Error),
+}
+
+impl Error {
+    /// Recovers the archive error carried by an IO error of an archive
+    /// stream.
+    pub fn from_stream(e: io::Error) -> Self {
+        // OS errors have no inner error, they stay as they are
+        if e.get_ref().is_none() {
+            return Error::Io(e);
+        }
+        let kind = e.kind();
+        match e
+            .into_inner()
+            .map(|inner| inner.downcast::<async_zip::error::Error>())
+        {
+            Some(Ok(inner)) => Error::Archive(*inner),
+            Some(Err(inner)) => Error::Io(io::Error::new(kind, inner)),
+            None => Error::Io(kind.into()),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
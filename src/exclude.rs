@@ -0,0 +1,170 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use regex::Regex;
+
+const REGEX_PREFIX: &str = "re:";
+
+/// Ignore files honored while walking, later ones take precedence.
+const IGNORE_FILES: [&str; 3] = [".gitignore", ".ignore", ".zipignore"];
+
+/// Exclusion patterns, relative to the input directory.
+///
+/// Patterns follow the gitignore syntax (`**/node_modules`, `*.log`,
+/// `target/`), patterns starting with `re:` are regular expressions matched
+/// against the relative path with `/` separators.
+#[derive(Clone)]
+pub struct Excludes {
+    root: PathBuf,
+    globs: Gitignore,
+    regexes: Vec<Regex>,
+}
+
+impl Excludes {
+    pub fn new(root: impl AsRef<Path>, patterns: &[String]) -> Result<Self> {
+        let root = root.as_ref();
+        let mut globs = GitignoreBuilder::new(root);
+        let mut regexes = vec![];
+
+        for pattern in patterns {
+            if let Some(regex) = pattern.strip_prefix(REGEX_PREFIX) {
+                regexes.push(Regex::new(regex)?);
+            } else {
+                globs
+                    .add_line(None, pattern)
+                    .map_err(|e| anyhow!("Invalid exclude pattern {}: {}", pattern, e))?;
+            }
+        }
+
+        Ok(Excludes {
+            root: root.to_owned(),
+            globs: globs.build()?,
+            regexes,
+        })
+    }
+
+    /// Excludes nothing.
+    pub fn empty() -> Self {
+        Excludes {
+            root: PathBuf::new(),
+            globs: Gitignore::empty(),
+            regexes: vec![],
+        }
+    }
+
+    /// Whether `path`, below the input directory, is excluded. The content of
+    /// an excluded directory is not checked, it is expected to be skipped.
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+
+        if self.globs.matched(relative, is_dir).is_ignore() {
+            return true;
+        }
+
+        if self.regexes.is_empty() {
+            return false;
+        }
+        let relative = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        self.regexes.iter().any(|regex| regex.is_match(&relative))
+    }
+}
+
+/// The ignore files of a directory and of its parents, like version control
+/// the deepest one matching a path decides whether it is ignored.
+#[derive(Clone, Default)]
+pub struct IgnoreFiles {
+    matchers: Vec<Arc<Gitignore>>,
+}
+
+impl IgnoreFiles {
+    /// Reads the ignore files of `dir` and of its parents up to `top`.
+    pub fn from_parents(top: &Path, dir: &Path) -> Self {
+        let mut dirs = dir
+            .ancestors()
+            .take_while(|parent| parent.starts_with(top))
+            .collect::<Vec<_>>();
+        if dirs.is_empty() {
+            dirs.push(dir);
+        }
+
+        dirs.iter()
+            .rev()
+            .fold(IgnoreFiles::default(), |ignores, dir| ignores.child(dir))
+    }
+
+    /// Adds the ignore files of `dir`, a child of the last added directory.
+    pub fn child(&self, dir: &Path) -> Self {
+        let mut matchers = self.matchers.clone();
+        if let Some(matcher) = read_ignore_files(dir) {
+            matchers.push(Arc::new(matcher));
+        }
+        IgnoreFiles { matchers }
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.matchers
+            .iter()
+            .rev()
+            .map(|matcher| matcher.matched(path, is_dir))
+            .find(|matched| !matched.is_none())
+            .map(|matched| matched.is_ignore())
+            .unwrap_or_default()
+    }
+}
+
+fn read_ignore_files(dir: &Path) -> Option<Gitignore> {
+    let mut builder = GitignoreBuilder::new(dir);
+    for name in IGNORE_FILES {
+        let path = dir.join(name);
+        if !path.is_file() {
+            continue;
+        }
+        if let Some(e) = builder.add(&path) {
+            eprintln!("invalid ignore file {path:?}: {e}");
+        }
+    }
+
+    builder.build().ok().filter(|matcher| !matcher.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::Excludes;
+
+    #[test]
+    fn is_excluded_should_work() {
+        let patterns = [
+            "**/node_modules",
+            "*.log",
+            "target/",
+            "/build",
+            r"re:^vendor/.*\.tmp$",
+        ]
+        .map(String::from);
+        let excludes = Excludes::new("/data", &patterns).unwrap();
+
+        let excluded = |path: &str, is_dir| excludes.is_excluded(Path::new(path), is_dir);
+
+        assert!(excluded("/data/web/app/node_modules", true));
+        assert!(excluded("/data/app/debug.log", false));
+        assert!(excluded("/data/app/target", true));
+        assert!(!excluded("/data/app/target", false));
+        assert!(excluded("/data/build", true));
+        assert!(!excluded("/data/app/build", true));
+        assert!(excluded("/data/vendor/lib/a.tmp", false));
+        assert!(!excluded("/data/app/vendor/a.tmp", false));
+        assert!(!excluded("/data/app/src/main.rs", false));
+
+        assert!(!Excludes::empty().is_excluded(Path::new("/data/app.log"), false));
+    }
+}
@@ -0,0 +1,224 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Result};
+use tokio::fs;
+
+use crate::async_zip::ZipReader;
+
+/// Restores the directory tree stored in `archive` under `output_dir`,
+/// `password` decrypts the encrypted entries.
+pub async fn extract(
+    archive: impl AsRef<Path>,
+    output_dir: impl AsRef<Path>,
+    password: Option<String>,
+) -> Result<()> {
+    let mut reader = ZipReader::open(archive).await?.password(password);
+    let output_dir = output_dir.as_ref();
+    fs::create_dir_all(output_dir).await?;
+
+    let entries = reader.entries().to_vec();
+    let mut dirs = vec![];
+    let mut links = vec![];
+    for entry in &entries {
+        let path = output_dir.join(entry_path(&entry.name)?);
+
+        if entry.is_dir() {
+            fs::create_dir_all(&path).await?;
+            dirs.push((path, entry.mode));
+            continue;
+        }
+
+        if entry.is_symlink() {
+            let mut target = vec![];
+            reader.read_entry(entry, &mut target).await?;
+            links.push((path, target));
+            continue;
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        println!("extract {path:?}");
+        let mut file = fs::File::create(&path).await?;
+        reader.read_entry(entry, &mut file).await?;
+        set_mode(&path, entry.mode).await?;
+    }
+
+    // links after the files, no entry is written through them
+    let root = fs::canonicalize(output_dir).await?;
+    for (path, target) in &links {
+        create_link(&root, path, target).await?;
+    }
+
+    // directories last, a read-only directory would refuse its content
+    for (path, mode) in dirs.iter().rev() {
+        set_mode(path, *mode).await?;
+    }
+
+    Ok(())
+}
+
+/// Creates the link `path` to `target`, inside the output directory `root`.
+async fn create_link(root: &Path, path: &Path, target: &[u8]) -> Result<()> {
+    // an earlier link must not lead the new one out of the output directory
+    let parent = path.parent().unwrap_or(root);
+    let existing = parent.ancestors().find(|p| p.exists()).unwrap_or(root);
+    if !fs::canonicalize(existing).await?.starts_with(root) {
+        bail!("Link {:?} escapes the output directory", path);
+    }
+    fs::create_dir_all(parent).await?;
+
+    println!("link {path:?}");
+    #[cfg(unix)]
+    {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        fs::symlink(OsStr::from_bytes(target), path).await?;
+    }
+    // without links, the link is a file holding its target
+    #[cfg(not(unix))]
+    fs::write(path, target).await?;
+
+    Ok(())
+}
+
+/// Restores the permissions of the Unix `mode` recorded in the archive.
+async fn set_mode(path: &Path, mode: Option<u32>) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::PermissionsExt;
+
+        let permissions = std::fs::Permissions::from_mode(mode & 0o7777);
+        fs::set_permissions(path, permissions).await?;
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+
+    Ok(())
+}
+
+/// Relative path of an entry, rejecting names that would escape the output
+/// directory (zip slip).
+fn entry_path(name: &str) -> Result<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(c) => path.push(c),
+            Component::CurDir => {}
+            _ => bail!("Entry {} escapes the output directory", name),
+        }
+    }
+
+    if path.as_os_str().is_empty() {
+        bail!("Entry {:?} has no path", name);
+    }
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use futures::StreamExt;
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::{
+        async_zip::{Compression, Zipper},
+        test_util::TempDir,
+    };
+
+    #[test]
+    fn entry_path_should_reject_zip_slip() {
+        assert_eq!(
+            entry_path("./src/main.rs").unwrap(),
+            Path::new("src/main.rs")
+        );
+        assert!(entry_path("../evil.sh").is_err());
+        assert!(entry_path("src/../../evil.sh").is_err());
+        assert!(entry_path("/etc/passwd").is_err());
+        assert!(entry_path("").is_err());
+    }
+
+    #[tokio::test]
+    async fn extract_should_restore_tree() -> Result<()> {
+        let root = TempDir::new("extract");
+        let archive = root.join("src.zip");
+        let output = root.join("src");
+
+        let zipper = Zipper::from_directory("src")
+            .await?
+            .compression(Compression::Deflate { level: 6 });
+        let mut stream = zipper.zipped_stream();
+        let mut f = fs::File::create(&archive).await?;
+        while let Some(chunk) = stream.next().await {
+            f.write_all(&chunk?).await?;
+        }
+        f.flush().await?;
+
+        extract(&archive, &output, None).await?;
+        assert_eq!(
+            fs::read(output.join("async_zip/zip.rs")).await?,
+            fs::read("src/async_zip/zip.rs").await?
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = |path: &Path| {
+                let meta = std::fs::metadata(path).unwrap();
+                meta.permissions().mode() & 0o7777
+            };
+            assert_eq!(
+                mode(&output.join("main.rs")),
+                mode(Path::new("src/main.rs"))
+            );
+            assert_eq!(
+                mode(&output.join("async_zip")),
+                mode(Path::new("src/async_zip"))
+            );
+        }
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn extract_should_restore_links() -> anyhow::Result<()> {
+        use crate::walk::{Symlinks, Walker};
+
+        let root = TempDir::new("extract_links");
+        let input = root.join("input");
+        let output = root.join("output");
+        fs::create_dir_all(&input).await?;
+        fs::write(input.join("file"), "content").await?;
+        fs::symlink("file", input.join("link")).await?;
+
+        let entries = Walker::default()
+            .symlinks(Symlinks::Store)
+            .walk(&input)
+            .await?;
+        let mut stream = Zipper::from_entries(entries.into_iter()).zipped_stream();
+        let archive = root.join("input.zip");
+        let mut f = fs::File::create(&archive).await?;
+        while let Some(chunk) = stream.next().await {
+            f.write_all(&chunk?).await?;
+        }
+        f.flush().await?;
+
+        extract(&archive, &output, None).await?;
+        assert_eq!(fs::read_link(output.join("link")).await?, Path::new("file"));
+        assert_eq!(fs::read(output.join("link")).await?, b"content");
+
+        // a link does not lead another one out of the output directory
+        fs::symlink("/", output.join("escape")).await?;
+        let output = fs::canonicalize(&output).await?;
+        assert!(create_link(&output, &output.join("escape/evil"), b"x")
+            .await
+            .is_err());
+
+        Ok(())
+    }
+}
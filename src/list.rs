@@ -0,0 +1,25 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::async_zip::ZipReader;
+
+/// Prints the entries of `archive` with their comments, after the archive
+/// comment.
+pub async fn list(archive: impl AsRef<Path>) -> Result<()> {
+    let reader = ZipReader::open(archive).await?;
+    if !reader.comment().is_empty() {
+        println!("{}", reader.comment());
+    }
+
+    for entry in reader.entries() {
+        if entry.comment.is_empty() {
+            println!("{:>12}  {}", entry.size, entry.name);
+        } else {
+            println!("{:>12}  {}  # {}", entry.size, entry.name, entry.comment);
+        }
+    }
+    println!("{} entries", reader.entries().len());
+
+    Ok(())
+}
@@ -1,34 +1,57 @@
 #![feature(is_some_and)]
 #![feature(result_option_inspect)]
 #![feature(option_result_contains)]
+#![allow(incomplete_features)]
 #![feature(async_fn_in_trait)]
+#![feature(return_position_impl_trait_in_trait)]
 #![feature(associated_type_defaults)]
 #![feature(type_alias_impl_trait)]
 
 mod async_zip;
+mod error;
+mod exclude;
+mod extract;
+mod list;
 mod option;
+mod output;
+#[cfg(test)]
+mod test_util;
+mod walk;
 mod zip_core;
 
 use option::Opt;
-use path_absolutize::*;
 
-use std::{
-    borrow::Cow,
-    env,
-    path::{Path, PathBuf},
-};
 use structopt::StructOpt;
 
 use anyhow::{bail, Result};
 
 use crate::{
-    option::ZipType,
-    zip_core::{AsyncZip, DirsZipEngine, Zip, ZipEngine, Zipper},
+    exclude::Excludes,
+    option::{Command, ZipType},
+    output::Output,
+    walk::Walker,
+    zip_core::{AsyncZip, DirsZipEngine, Tar, Zip, ZipEngine, Zipper},
 };
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let opt = Opt::from_args();
+
+    if let Some(Command::Extract {
+        archive,
+        output_dir,
+    }) = &opt.command
+    {
+        let output_dir = output_dir
+            .clone()
+            .unwrap_or_else(|| archive.with_extension(""));
+        return extract::extract(archive, output_dir, opt.password()?).await;
+    }
+    if let Some(Command::List { archive }) = &opt.command {
+        return list::list(archive).await;
+    }
+
+    opt.check_zip_type()?;
     let dir = opt.input_dir.as_path();
 
     if !dir.exists() {
@@ -36,100 +59,103 @@ async fn main() -> Result<()> {
     }
 
     println!(
-        "input directory is: {:?}, zip_type is: {:?}, exclude_dir is: {:?}",
-        dir, opt.zip_type, opt.exclude_dir
+        "input directory is: {:?}, zip_type is: {:?}, exclude is: {:?}",
+        dir, opt.zip_type, opt.exclude
     );
 
-    let excluded = opt.exclude_dir.clone();
-
-    match opt.zip_type {
-        ZipType::Zip => DirsZipEngine::new(Zip {}, &dir, excluded).do_zip().await,
-        ZipType::Zipper => DirsZipEngine::new(Zipper {}, &dir, excluded).do_zip().await,
-        ZipType::AsyncZip => {
-            DirsZipEngine::new(AsyncZip {}, &dir, excluded)
-                .do_zip()
-                .await
-        }
+    let excludes = Excludes::new(dir, &opt.exclude)?;
+    let fixed_time = opt.fixed_time()?;
+    let mut walker = Walker::new(excludes.clone())
+        .sorted(opt.reproducible)
+        .symlinks(opt.symlinks)
+        .non_utf8_names(opt.non_utf8_names);
+    if opt.ignore_files {
+        walker = walker.ignore_files(dir);
     }
-}
-
-#[allow(deprecated)]
-fn absolute_path<T: AsRef<Path>>(cwd: Option<T>, path: &Path) -> Cow<'_, Path> {
-    let home = env::home_dir().unwrap();
-
-    if path.starts_with("~/") {
-        let path = path.strip_prefix("~/").unwrap();
-        path.absolutize_virtually(home).unwrap()
-    } else {
-        if let Some(cwd) = cwd {
-            path.absolutize_from(&cwd.as_ref().absolutize().unwrap())
-                .unwrap()
-        } else {
-            path.absolutize_virtually(home).unwrap()
-        }
+    let (min_depth, max_depth) = (opt.min_depth(), opt.depth);
+    if min_depth > max_depth {
+        bail!("Minimal depth {} is over depth {}", min_depth, max_depth);
     }
-}
-
-fn is_exclude(cwd: Option<&Path>, exclude: &Vec<PathBuf>, dir: impl AsRef<Path>) -> bool {
-    if exclude.is_empty() {
-        return false;
+    if let Some(output_dir) = &opt.output_dir {
+        tokio::fs::create_dir_all(output_dir).await?;
     }
-    let exclude: Vec<Cow<Path>> = exclude
-        .iter()
-        .map(|path| absolute_path(cwd, &path))
-        .collect();
-
-    let absolute_dir = absolute_path(cwd, dir.as_ref());
-
-    println!("exclude dirs {:?}, dir {:?}", exclude, absolute_dir);
-
-    exclude.iter().find(|x| **x == absolute_dir).is_some()
-}
+    let output = Output::new(opt.output_dir.clone(), &opt.name_template)?;
 
-#[cfg(test)]
-mod test {
-    use std::{borrow::Borrow, path::Path};
-
-    use path_absolutize::Absolutize;
-
-    use crate::is_exclude;
-
-    #[test]
-    fn absolutize_from_should_work() {
-        let path1 = Path::new("src/async_zip");
-        let path = path1.absolutize_from(Path::new("/ss/bb")).unwrap();
-        assert_eq!(
-            Path::new("/ss/bb/src/async_zip"),
-            Borrow::<Path>::borrow(&path)
-        );
-
-        let path1 = Path::new("/src/async_zip");
-        let path = path1.absolutize_from(Path::new("/ss/bb")).unwrap();
-        assert_eq!(Path::new("/src/async_zip"), Borrow::<Path>::borrow(&path))
+    if opt.jobs == 0 {
+        bail!("Jobs must be at least 1");
     }
 
-    #[test]
-    fn is_exclude_should_work() {
-        let path1 = Path::new("./src/async_zip");
-        let path2 = Path::new("~/Rust/zip_dirs/src/async_zip");
-        let path3 = Path::new("../async_zip");
-        let path4 = Path::new("./src/async_zip");
-
-        let ok = is_exclude(
-            Some(Path::new(".").as_ref()),
-            &vec![path1.to_path_buf()],
-            path2,
-        );
-        assert!(ok);
-
-        let ok = is_exclude(
-            Some(Path::new(".").as_ref()),
-            &vec![path1.to_path_buf()],
-            path3,
-        );
-        assert!(!ok);
+    let summary = match opt.zip_type {
+        ZipType::Zip => {
+            let zip = Zip::new(walker)
+                .fixed_time(fixed_time)
+                .normalize_modes(opt.reproducible);
+            DirsZipEngine::new(zip, dir, excludes)
+                .depth(min_depth, max_depth)
+                .single(opt.single)
+                .output(output)
+                .jobs(opt.jobs)
+                .do_zip()
+                .await?
+        }
+        ZipType::Tar | ZipType::TarGz | ZipType::TarZst => {
+            let tar = Tar::new(opt.tar_compression()?, walker)
+                .fixed_time(fixed_time)
+                .normalize_modes(opt.reproducible);
+            DirsZipEngine::new(tar, dir, excludes)
+                .depth(min_depth, max_depth)
+                .single(opt.single)
+                .output(output)
+                .jobs(opt.jobs)
+                .do_zip()
+                .await?
+        }
+        ZipType::Zipper => {
+            let zipper = Zipper::new(opt.compression()?, walker)
+                .policy(opt.compression_policy())
+                .fixed_time(fixed_time)
+                .utc(opt.utc)
+                .comment(opt.comment.clone())
+                .source_comments(opt.source_comments)
+                .password(opt.password()?)
+                .split_size(opt.split_size)
+                .normalize_modes(opt.reproducible);
+            DirsZipEngine::new(zipper, dir, excludes)
+                .depth(min_depth, max_depth)
+                .single(opt.single)
+                .output(output)
+                .jobs(opt.jobs)
+                .do_zip()
+                .await?
+        }
+        ZipType::AsyncZip => {
+            let async_zip = AsyncZip::new(walker)
+                .compression(opt.compression()?)?
+                .policy(opt.compression_policy())
+                .memory_budget(opt.memory_budget()?)
+                .fixed_time(fixed_time)
+                .normalize_modes(opt.reproducible);
+            DirsZipEngine::new(async_zip, dir, excludes)
+                .depth(min_depth, max_depth)
+                .single(opt.single)
+                .output(output)
+                .jobs(opt.jobs)
+                .do_zip()
+                .await?
+        }
+    };
 
-        let ok = is_exclude(None, &vec![path1.to_path_buf()], path4);
-        assert!(ok);
+    println!(
+        "{} directories zipped, {} failed",
+        summary.succeeded.len(),
+        summary.failed.len()
+    );
+    if !summary.failed.is_empty() {
+        for (directory, e) in &summary.failed {
+            println!("  {directory:?}: {e:#}");
+        }
+        bail!("Failed to zip {} directories", summary.failed.len());
     }
+
+    Ok(())
 }
@@ -1,6 +1,25 @@
-use std::{ops::Deref, path::PathBuf};
+use std::{
+    ops::RangeInclusive,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
 use structopt::StructOpt;
 
+use crate::{
+    async_zip::{Compression, CompressionPolicy, COMPRESSED_EXTENSIONS},
+    walk::{NonUtf8Names, Symlinks},
+    zip_core::{TarCompression, DEFAULT_MEMORY_BUDGET},
+};
+
+/// 1980-01-01 00:00:00 UTC, the first zip date, in seconds since the Unix epoch
+const DOS_EPOCH: u64 = 315_532_800;
+
+/// Smallest volume of a split archive, every header fits in it
+const MIN_SPLIT_SIZE: u64 = 64 * 1024;
+
+/// Compression level when none is given
+const DEFAULT_LEVEL: u32 = 6;
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "zip_dirs", about = "squash things in directories")]
 pub(crate) struct Opt {
@@ -8,13 +27,282 @@ pub(crate) struct Opt {
     #[structopt(parse(from_os_str), default_value = ".")]
     pub(crate) input_dir: PathBuf,
 
-    /// Zip type, optional value is zip or zipper
+    /// Zip type, optional value is zip, async_zip, self_async_zip, tar, tar.gz or tar.zst
     #[structopt(short, parse(try_from_str = parse_zip_type), default_value = "zip")]
     pub(crate) zip_type: ZipType,
 
-    /// Exclude dir
-    #[structopt(short = "e", long = "exclude-dir", default_value = "")]
-    pub(crate) exclude_dir: Dirs,
+    /// Exclude pattern relative to the input directory, gitignore style (`**/node_modules`,
+    /// `*.log`, `target/`) or a regular expression prefixed with `re:`, can be repeated
+    #[structopt(short = "e", long = "exclude", number_of_values = 1)]
+    pub(crate) exclude: Vec<String>,
+
+    /// Skip what .gitignore, .ignore and .zipignore files ignore inside the zipped directories
+    #[structopt(long)]
+    pub(crate) ignore_files: bool,
+
+    /// What to do with symlinks, optional value is follow, store or skip
+    #[structopt(long, parse(try_from_str = parse_symlinks), default_value = "follow")]
+    pub(crate) symlinks: Symlinks,
+
+    /// What to do with file names that are not valid UTF-8, optional value is error, lossy
+    /// (replace invalid bytes with U+FFFD) or escape (write them as %XX)
+    #[structopt(long, parse(try_from_str = parse_non_utf8_names), default_value = "error")]
+    pub(crate) non_utf8_names: NonUtf8Names,
+
+    /// Depth below the input directory of the directories to zip, its children are at depth 1
+    #[structopt(short, long, default_value = "1")]
+    pub(crate) depth: usize,
+
+    /// Minimal depth of the directories to zip, defaults to depth
+    #[structopt(long)]
+    pub(crate) min_depth: Option<usize>,
+
+    /// Zip all directories into a single archive of the input directory
+    #[structopt(short, long)]
+    pub(crate) single: bool,
+
+    /// Directory of the archives, defaults to beside each zipped directory
+    #[structopt(short, long, parse(from_os_str))]
+    pub(crate) output_dir: Option<PathBuf>,
+
+    /// Archive name template, placeholders are {name}, {parent}, {date} (YYYY-MM-DD) and {ext}
+    #[structopt(short = "t", long = "name-template", default_value = "{name}.{ext}")]
+    pub(crate) name_template: String,
+
+    /// Number of directories zipped at the same time
+    #[structopt(short, long, default_value = "1")]
+    pub(crate) jobs: usize,
+
+    /// Memory budget of the async_zip zip type in MiB per archive, bounds the file data read
+    /// ahead of each archive being written, defaults to 64
+    #[structopt(long)]
+    pub(crate) memory_budget: Option<usize>,
+
+    /// Write byte-identical archives for identical inputs: sorted entries and timestamps set to
+    /// SOURCE_DATE_EPOCH in UTC, or to 1980-01-01 when not set. Not with --password, encrypted
+    /// archives have a random salt
+    #[structopt(long)]
+    pub(crate) reproducible: bool,
+
+    /// Write the DOS modification times of the self_async_zip zip type in UTC instead of local
+    /// time, the extended timestamp is always in UTC
+    #[structopt(long)]
+    pub(crate) utc: bool,
+
+    /// Archive comment of the self_async_zip zip type, e.g. a build ID
+    #[structopt(long)]
+    pub(crate) comment: Option<String>,
+
+    /// Comment each entry of the self_async_zip zip type with its source path
+    #[structopt(long)]
+    pub(crate) source_comments: bool,
+
+    /// Encrypt the archives of the self_async_zip zip type with AES-256, or decrypt the
+    /// extracted ones, with the password of the environment variable NAME (env:NAME) or typed
+    /// in (prompt)
+    #[structopt(long, parse(try_from_str = parse_password))]
+    pub(crate) password: Option<PasswordSource>,
+
+    /// Split the archives of the self_async_zip zip type in volumes of at most this size, in
+    /// bytes or with a k, m or g suffix, at least 64k: name.z01, name.z02, ... and name.zip last
+    #[structopt(long, parse(try_from_str = parse_split_size))]
+    pub(crate) split_size: Option<u64>,
+
+    /// Compression method of the async_zip and self_async_zip zip types, optional value is
+    /// store, deflate, zstd, bzip2 or lzma, defaults to deflate, async_zip does not support lzma
+    #[structopt(short, long, parse(try_from_str = parse_compression))]
+    pub(crate) compression: Option<CompressionMethod>,
+
+    /// Compression level of the self_async_zip, tar.gz and tar.zst zip types, defaults to 6: 0 - 9
+    /// for deflate, gzip and lzma, 1 - 9 for bzip2, 0 - 22 for zstd where 0 is its default level
+    #[structopt(short, long)]
+    pub(crate) level: Option<u32>,
+
+    /// Store without compression the files with these extensions, comma separated, e.g.
+    /// iso,parquet, for the async_zip and self_async_zip zip types
+    #[structopt(long, use_delimiter = true)]
+    pub(crate) store_extensions: Vec<String>,
+
+    /// Store without compression the files of common compressed formats: images, audio, video
+    /// and archives such as jpg, mp4, gz and zip, for the async_zip and self_async_zip zip types
+    #[structopt(long)]
+    pub(crate) store_compressed: bool,
+
+    /// Store without compression the files whose first 64 KiB do not compress, for the
+    /// async_zip and self_async_zip zip types
+    #[structopt(long)]
+    pub(crate) sample_compression: bool,
+
+    #[structopt(subcommand)]
+    pub(crate) command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum Command {
+    /// Extract an archive
+    Extract {
+        /// Archive path
+        #[structopt(parse(from_os_str))]
+        archive: PathBuf,
+
+        /// Output directory, defaults to the archive path without extension
+        #[structopt(parse(from_os_str))]
+        output_dir: Option<PathBuf>,
+    },
+    /// List the entries of an archive with their comments
+    List {
+        /// Archive path
+        #[structopt(parse(from_os_str))]
+        archive: PathBuf,
+    },
+}
+
+impl Opt {
+    pub(crate) fn min_depth(&self) -> usize {
+        self.min_depth.unwrap_or(self.depth)
+    }
+
+    /// Timestamp of every entry in reproducible mode.
+    pub(crate) fn fixed_time(&self) -> anyhow::Result<Option<SystemTime>> {
+        if !self.reproducible {
+            return Ok(None);
+        }
+        let epoch = match std::env::var("SOURCE_DATE_EPOCH") {
+            Ok(epoch) => epoch
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid SOURCE_DATE_EPOCH {}: {}", epoch, e))?,
+            Err(_) => DOS_EPOCH,
+        };
+        // zip dates start in 1980
+        let epoch = Duration::from_secs(epoch.max(DOS_EPOCH));
+        Ok(Some(SystemTime::UNIX_EPOCH + epoch))
+    }
+
+    pub(crate) fn password(&self) -> anyhow::Result<Option<String>> {
+        let password = match &self.password {
+            None => return Ok(None),
+            Some(PasswordSource::Env(name)) => std::env::var(name)
+                .map_err(|e| anyhow::anyhow!("Cannot read password from {}: {}", name, e))?,
+            Some(PasswordSource::Prompt) => rpassword::prompt_password("Password: ")?,
+        };
+        if password.is_empty() {
+            anyhow::bail!("Password is empty");
+        }
+        Ok(Some(password))
+    }
+
+    /// Memory budget of the async_zip zip type in bytes.
+    pub(crate) fn memory_budget(&self) -> anyhow::Result<usize> {
+        match self.memory_budget {
+            None => Ok(DEFAULT_MEMORY_BUDGET),
+            Some(mib) => mib
+                .checked_mul(1 << 20)
+                .ok_or_else(|| anyhow::anyhow!("Memory budget {} MiB is too big", mib)),
+        }
+    }
+
+    /// Fails when an option is given with a zip type that does not use it,
+    /// rather than ignoring it, or with an option it contradicts.
+    pub(crate) fn check_zip_type(&self) -> anyhow::Result<()> {
+        if self.reproducible && self.password.is_some() {
+            anyhow::bail!(
+                "--reproducible cannot be used with --password, encryption salts are random"
+            );
+        }
+        let zipper = matches!(self.zip_type, ZipType::Zipper);
+        let async_zip = matches!(self.zip_type, ZipType::AsyncZip);
+        let compressed_tar = matches!(self.zip_type, ZipType::TarGz | ZipType::TarZst);
+        // the async_zip crate has no levels for streamed entries
+        let options = [
+            ("--password", self.password.is_some(), zipper),
+            ("--split-size", self.split_size.is_some(), zipper),
+            ("--comment", self.comment.is_some(), zipper),
+            ("--source-comments", self.source_comments, zipper),
+            ("--utc", self.utc, zipper),
+            (
+                "--compression",
+                self.compression.is_some(),
+                zipper || async_zip,
+            ),
+            ("--level", self.level.is_some(), zipper || compressed_tar),
+            (
+                "--store-extensions",
+                !self.store_extensions.is_empty(),
+                zipper || async_zip,
+            ),
+            (
+                "--store-compressed",
+                self.store_compressed,
+                zipper || async_zip,
+            ),
+            (
+                "--sample-compression",
+                self.sample_compression,
+                zipper || async_zip,
+            ),
+            ("--memory-budget", self.memory_budget.is_some(), async_zip),
+        ];
+        if let Some((option, ..)) = options
+            .iter()
+            .find(|(_, given, supported)| *given && !supported)
+        {
+            anyhow::bail!(
+                "{} is not supported by the {:?} zip type",
+                option,
+                self.zip_type
+            );
+        }
+        Ok(())
+    }
+
+    /// Which files are stored instead of compressed.
+    pub(crate) fn compression_policy(&self) -> CompressionPolicy {
+        let mut policy = CompressionPolicy::default()
+            .store_extensions(&self.store_extensions)
+            .sample(self.sample_compression);
+        if self.store_compressed {
+            policy = policy.store_extensions(COMPRESSED_EXTENSIONS);
+        }
+        policy
+    }
+
+    fn level(&self) -> u32 {
+        self.level.unwrap_or(DEFAULT_LEVEL)
+    }
+
+    /// Compression of the tar zip types.
+    pub(crate) fn tar_compression(&self) -> anyhow::Result<TarCompression> {
+        Ok(match self.zip_type {
+            ZipType::TarGz => TarCompression::Gzip {
+                level: check_level("gzip", self.level(), 0..=9)?,
+            },
+            ZipType::TarZst => TarCompression::Zstd {
+                level: check_level("zstd", self.level(), 0..=22)?,
+            },
+            _ => TarCompression::None,
+        })
+    }
+
+    pub(crate) fn compression(&self) -> anyhow::Result<Compression> {
+        let level = self.level();
+        Ok(
+            match self.compression.unwrap_or(CompressionMethod::Deflate) {
+                CompressionMethod::Store => Compression::Store,
+                CompressionMethod::Deflate => Compression::Deflate {
+                    level: check_level("deflate", level, 0..=9)?,
+                },
+                CompressionMethod::Zstd => Compression::Zstd {
+                    level: check_level("zstd", level, 0..=22)?,
+                },
+                CompressionMethod::Bzip2 => Compression::Bzip2 {
+                    level: check_level("bzip2", level, 1..=9)?,
+                },
+                CompressionMethod::Lzma => Compression::Lzma {
+                    level: check_level("lzma", level, 0..=9)?,
+                },
+            },
+        )
+    }
 }
 
 #[derive(Debug)]
@@ -22,6 +310,9 @@ pub(crate) enum ZipType {
     AsyncZip,
     Zipper,
     Zip,
+    Tar,
+    TarGz,
+    TarZst,
 }
 
 fn parse_zip_type(src: &str) -> Result<ZipType, anyhow::Error> {
@@ -29,31 +320,92 @@ fn parse_zip_type(src: &str) -> Result<ZipType, anyhow::Error> {
         "async_zip" => Ok(ZipType::AsyncZip),
         "self_async_zip" => Ok(ZipType::Zipper),
         "zip" => Ok(ZipType::Zip),
+        "tar" => Ok(ZipType::Tar),
+        "tar.gz" => Ok(ZipType::TarGz),
+        "tar.zst" => Ok(ZipType::TarZst),
         _ => Err(anyhow::anyhow!("Not support")),
     }
 }
 
-#[derive(Debug)]
-pub struct Dirs(Vec<PathBuf>);
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum CompressionMethod {
+    Store,
+    Deflate,
+    Zstd,
+    Bzip2,
+    Lzma,
+}
+
+fn parse_compression(src: &str) -> Result<CompressionMethod, anyhow::Error> {
+    match src {
+        "store" => Ok(CompressionMethod::Store),
+        "deflate" => Ok(CompressionMethod::Deflate),
+        "zstd" => Ok(CompressionMethod::Zstd),
+        "bzip2" => Ok(CompressionMethod::Bzip2),
+        "lzma" => Ok(CompressionMethod::Lzma),
+        _ => Err(anyhow::anyhow!("Not support")),
+    }
+}
 
-impl Deref for Dirs {
-    type Target = Vec<PathBuf>;
+fn parse_symlinks(src: &str) -> Result<Symlinks, anyhow::Error> {
+    match src {
+        "follow" => Ok(Symlinks::Follow),
+        "store" => Ok(Symlinks::Store),
+        "skip" => Ok(Symlinks::Skip),
+        _ => Err(anyhow::anyhow!("Not support")),
+    }
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+fn parse_non_utf8_names(src: &str) -> Result<NonUtf8Names, anyhow::Error> {
+    match src {
+        "error" => Ok(NonUtf8Names::Error),
+        "lossy" => Ok(NonUtf8Names::Lossy),
+        "escape" => Ok(NonUtf8Names::Escape),
+        _ => Err(anyhow::anyhow!("Not support")),
     }
 }
 
-impl std::str::FromStr for Dirs {
-    type Err = Box<dyn std::error::Error>;
+#[derive(Debug)]
+pub(crate) enum PasswordSource {
+    Env(String),
+    Prompt,
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.len() == 0 {
-            Ok(Dirs(Vec::new()))
-        } else {
-            Ok(Dirs(
-                s.split(",").map(|x| x.trim().to_owned().into()).collect(),
-            ))
-        }
+fn parse_password(src: &str) -> Result<PasswordSource, anyhow::Error> {
+    match src.strip_prefix("env:") {
+        Some(name) if !name.is_empty() => Ok(PasswordSource::Env(name.to_owned())),
+        Some(_) => Err(anyhow::anyhow!("Not support")),
+        None if src == "prompt" => Ok(PasswordSource::Prompt),
+        None => Err(anyhow::anyhow!("Not support")),
+    }
+}
+
+fn parse_split_size(src: &str) -> Result<u64, anyhow::Error> {
+    let (number, unit) = match src.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&src[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&src[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&src[..i], 1 << 30),
+        _ => (src, 1),
+    };
+    let size = number
+        .parse::<u64>()?
+        .checked_mul(unit)
+        .ok_or_else(|| anyhow::anyhow!("Split size {} is too big", src))?;
+    if size < MIN_SPLIT_SIZE {
+        return Err(anyhow::anyhow!("Split size {} is below 64k", src));
+    }
+    Ok(size)
+}
+
+fn check_level(method: &str, level: u32, levels: RangeInclusive<u32>) -> anyhow::Result<u32> {
+    if !levels.contains(&level) {
+        anyhow::bail!(
+            "Level {} out of range {} - {} of {}",
+            level,
+            levels.start(),
+            levels.end(),
+            method
+        );
     }
+    Ok(level)
 }
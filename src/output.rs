@@ -0,0 +1,189 @@
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+
+use crate::error::{Error, Result};
+
+const DEFAULT_TEMPLATE: &str = "{name}.{ext}";
+const PLACEHOLDERS: [&str; 4] = ["name", "parent", "date", "ext"];
+
+/// Where archives are written and how they are named.
+///
+/// The name template can use `{name}` (the zipped directory name),
+/// `{parent}` (the name of its parent), `{date}` (today as `YYYY-MM-DD`) and
+/// `{ext}` (the archive extension).
+#[derive(Clone)]
+pub struct Output {
+    /// Output directory, archives are written beside the zipped directories
+    /// when not set
+    dir: Option<PathBuf>,
+    template: String,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output {
+            dir: None,
+            template: DEFAULT_TEMPLATE.to_owned(),
+        }
+    }
+}
+
+impl Output {
+    pub fn new(dir: Option<PathBuf>, template: impl Into<String>) -> Result<Self> {
+        let template = template.into();
+        // fail early on unknown placeholders
+        render(&template, &PLACEHOLDERS.map(|key| (key, key)))?;
+
+        Ok(Output { dir, template })
+    }
+
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    /// Archive path of the directory `path`, `ext` is the archive extension.
+    pub async fn archive_path(&self, path: &Path, ext: &str) -> Result<PathBuf> {
+        let canonicalize = |resolved: &Path| {
+            let resolved = resolved.to_owned();
+            async move {
+                tokio::fs::canonicalize(&resolved)
+                    .await
+                    .map_err(|source| Error::Walk {
+                        path: path.to_owned(),
+                        source,
+                    })
+            }
+        };
+        // a link to a directory is named after the link, not its target, so
+        // only `.` and `..` are resolved
+        let path = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) if parent.as_os_str().is_empty() => {
+                canonicalize(Path::new(".")).await?.join(name)
+            }
+            (Some(parent), Some(name)) => canonicalize(parent).await?.join(name),
+            _ => canonicalize(path).await?,
+        };
+        let file_name = |path: Option<&Path>| {
+            path.and_then(|p| p.file_name())
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        let name = file_name(Some(&path));
+        let parent = file_name(path.parent());
+        let date = Local::now().format("%Y-%m-%d").to_string();
+
+        let archive = render(
+            &self.template,
+            &[
+                ("name", &name),
+                ("parent", &parent),
+                ("date", &date),
+                ("ext", ext),
+            ],
+        )?;
+        if Path::new(&archive).file_name() != Some(archive.as_ref()) {
+            return Err(Error::InvalidName(format!(
+                "{archive:?} of {path:?} is not a file name"
+            )));
+        }
+
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => path
+                .parent()
+                .ok_or_else(|| Error::InvalidName(format!("{path:?} has no parent directory")))?
+                .to_owned(),
+        };
+        Ok(dir.join(archive))
+    }
+}
+
+/// Replaces the `{key}` placeholders of `template`.
+fn render(template: &str, vars: &[(&str, &str)]) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| Error::Template(format!("{template}, unclosed placeholder")))?;
+        let key = &rest[start + 1..start + end];
+        let (_, value) = vars
+            .iter()
+            .find(|(k, _)| *k == key)
+            .ok_or_else(|| Error::Template(format!("{template}, unknown placeholder {{{key}}}")))?;
+        rendered.push_str(value);
+        rest = &rest[start + end + 1..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use chrono::Local;
+
+    use super::*;
+
+    #[test]
+    fn render_should_replace_placeholders() {
+        let vars = [("name", "v1.2"), ("parent", "releases")];
+        assert_eq!(
+            render("{parent}_{name}.zip", &vars).unwrap(),
+            "releases_v1.2.zip"
+        );
+        assert!(render("{name", &vars).is_err());
+        assert!(render("{version}.zip", &vars).is_err());
+        assert!(Output::new(None, "{name}-{hash}.zip").is_err());
+    }
+
+    #[tokio::test]
+    async fn archive_path_should_follow_template() -> Result<()> {
+        let src = tokio::fs::canonicalize("src").await?;
+
+        let beside = Output::default()
+            .archive_path(Path::new("src"), "zip")
+            .await?;
+        assert_eq!(beside, src.with_file_name("src.zip"));
+
+        let output = Output::new(Some(PathBuf::from("/out")), "{parent}_{name}-{date}.{ext}")?;
+        let archive = output
+            .archive_path(Path::new("src/async_zip"), "zip")
+            .await?;
+        let date = Local::now().format("%Y-%m-%d");
+        assert_eq!(
+            archive,
+            PathBuf::from(format!("/out/src_async_zip-{date}.zip"))
+        );
+
+        let output = Output::new(None, "{parent}/{name}")?;
+        assert!(output.archive_path(Path::new("src"), "zip").await.is_err());
+
+        let archive = Output::default()
+            .archive_path(Path::new("src/async_zip/.."), "zip")
+            .await?;
+        assert_eq!(archive, src.with_file_name("src.zip"));
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn archive_path_should_name_links_after_themselves() -> Result<()> {
+        let root = crate::test_util::TempDir::new("archive_path_links");
+        std::fs::create_dir(root.join("target"))?;
+        std::os::unix::fs::symlink(root.join("target"), root.join("link"))?;
+
+        let archive = Output::default()
+            .archive_path(&root.join("link"), "zip")
+            .await?;
+        assert_eq!(archive.file_name().unwrap(), "link.zip");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+//! Helpers shared by the tests.
+
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+/// Directory of a test under the system temporary directory, removed with
+/// its content when dropped, also when the test fails.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates the empty directory `zip_dirs_{name}_{pid}`.
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("zip_dirs_{}_{}", name, std::process::id()));
+        // left over by a killed run
+        std::fs::remove_dir_all(&path).ok();
+        std::fs::create_dir_all(&path).expect("cannot create temporary directory");
+        TempDir(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+/// `len` bytes of xorshift noise, which does not compress.
+pub(crate) fn noise(len: usize) -> Vec<u8> {
+    let mut x: u32 = 1;
+    (0..len)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x as u8
+        })
+        .collect()
+}
@@ -0,0 +1,495 @@
+use std::{
+    borrow::Cow,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{Error, Result},
+    exclude::{Excludes, IgnoreFiles},
+};
+
+/// A file or directory to put in an archive.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Path on disk
+    pub path: PathBuf,
+    /// Name inside the archive, relative to the zipped directory with `/`
+    /// separators, directory names end with `/`
+    pub name: String,
+    /// Target of a symlink stored as a link
+    pub link: Option<PathBuf>,
+}
+
+impl Entry {
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+
+    /// Content of a link entry, the path it points to.
+    pub fn link_target(&self) -> Option<Vec<u8>> {
+        let link = self.link.as_ref()?;
+        #[cfg(unix)]
+        let target = {
+            use std::os::unix::ffi::OsStrExt;
+            link.as_os_str().as_bytes().to_vec()
+        };
+        #[cfg(not(unix))]
+        let target = link.to_string_lossy().replace('\\', "/").into_bytes();
+        Some(target)
+    }
+}
+
+/// What to do with symlinks met while walking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Symlinks {
+    /// Zips what links point to, skipping links to a directory that contains
+    /// them
+    #[default]
+    Follow,
+    /// Zips links as links, their target is the entry content
+    Store,
+    /// Leaves links out
+    Skip,
+}
+
+/// What to do with file names that are not valid UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NonUtf8Names {
+    /// Fails the archive
+    #[default]
+    Error,
+    /// Replaces invalid bytes with U+FFFD
+    Lossy,
+    /// Replaces invalid bytes with `%XX`
+    Escape,
+}
+
+impl NonUtf8Names {
+    /// `name` as UTF-8, `None` if it is not and the policy is to fail.
+    pub fn convert(self, name: &OsStr) -> Option<Cow<'_, str>> {
+        if let Some(name) = name.to_str() {
+            return Some(Cow::Borrowed(name));
+        }
+        match self {
+            NonUtf8Names::Error => None,
+            NonUtf8Names::Lossy => Some(name.to_string_lossy()),
+            NonUtf8Names::Escape => Some(Cow::Owned(escape(name))),
+        }
+    }
+}
+
+/// `name` with the bytes that are not part of a UTF-8 sequence written as
+/// `%XX`, names are UTF-16 outside Unix and only get the lossy conversion.
+fn escape(name: &OsStr) -> String {
+    #[cfg(unix)]
+    {
+        use std::{fmt::Write, os::unix::ffi::OsStrExt};
+
+        let mut escaped = String::new();
+        let mut rest = name.as_bytes();
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    escaped.push_str(valid);
+                    return escaped;
+                }
+                Err(e) => {
+                    let (valid, invalid) = rest.split_at(e.valid_up_to());
+                    escaped.push_str(&String::from_utf8_lossy(valid));
+                    let len = e.error_len().unwrap_or(invalid.len());
+                    for byte in &invalid[..len] {
+                        let _ = write!(escaped, "%{byte:02X}");
+                    }
+                    rest = &invalid[len..];
+                }
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    name.to_string_lossy().into_owned()
+}
+
+/// Archive name of `path` relative to `root`, names that are not valid UTF-8
+/// are converted as `names` says.
+fn entry_name(root: &Path, path: &Path, is_dir: bool, names: NonUtf8Names) -> Result<String> {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let mut name = relative
+        .components()
+        .map(|c| names.convert(c.as_os_str()))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| Error::Encoding(path.to_owned()))?
+        .join("/");
+    if is_dir {
+        name.push('/');
+    }
+    Ok(name)
+}
+
+/// Lists the entries to put in an archive.
+#[derive(Clone)]
+pub struct Walker {
+    excludes: Excludes,
+    /// Top directory of the ignore files to honor, if enabled
+    ignore_top: Option<PathBuf>,
+    /// Whether directory contents are listed by name
+    sorted: bool,
+    symlinks: Symlinks,
+    names: NonUtf8Names,
+}
+
+impl Default for Walker {
+    fn default() -> Self {
+        Walker::new(Excludes::empty())
+    }
+}
+
+impl Walker {
+    pub fn new(excludes: Excludes) -> Self {
+        Walker {
+            excludes,
+            ignore_top: None,
+            sorted: false,
+            symlinks: Symlinks::default(),
+            names: NonUtf8Names::default(),
+        }
+    }
+
+    pub fn non_utf8_names(mut self, names: NonUtf8Names) -> Self {
+        self.names = names;
+        self
+    }
+
+    pub fn symlinks(mut self, symlinks: Symlinks) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    pub fn follows_symlinks(&self) -> bool {
+        self.symlinks == Symlinks::Follow
+    }
+
+    /// Lists the content of each directory sorted by name instead of in the
+    /// file system order, for reproducible archives.
+    pub fn sorted(mut self, sorted: bool) -> Self {
+        self.sorted = sorted;
+        self
+    }
+
+    /// Skips what `.gitignore`, `.ignore` and `.zipignore` files ignore, the
+    /// ones in the walked directory, in its subdirectories and in its parents
+    /// up to `top`. The `.git` directory is skipped too.
+    pub fn ignore_files(mut self, top: impl AsRef<Path>) -> Self {
+        self.ignore_top = Some(top.as_ref().to_owned());
+        self
+    }
+
+    /// Recursively lists the directories and files under `root`, every
+    /// directory comes before its content. Excluded directories are skipped
+    /// with their content.
+    pub async fn walk(&self, root: impl AsRef<Path>) -> Result<Vec<Entry>> {
+        self.walk_named(root.as_ref(), root.as_ref()).await
+    }
+
+    /// Lists `root` itself and its content, named relative to `top`, to put
+    /// several directories in one archive.
+    pub async fn walk_relative(&self, top: &Path, root: &Path) -> Result<Vec<Entry>> {
+        let mut entries = vec![Entry {
+            path: root.to_owned(),
+            name: entry_name(top, root, true, self.names)?,
+            link: None,
+        }];
+        entries.extend(self.walk_named(root, top).await?);
+        Ok(entries)
+    }
+
+    /// Walks `root`, naming entries relative to `name_root`.
+    async fn walk_named(&self, root: &Path, name_root: &Path) -> Result<Vec<Entry>> {
+        let ignores = self
+            .ignore_top
+            .as_ref()
+            .map(|top| IgnoreFiles::from_parents(top, root));
+        // canonical paths of the walked directory and of its parents, a
+        // followed link to one of them is a cycle
+        let canonical = tokio::fs::canonicalize(root)
+            .await
+            .map_err(|source| Error::Walk {
+                path: root.to_owned(),
+                source,
+            })?;
+        let mut dirs = vec![(root.to_owned(), ignores, vec![canonical])];
+        let mut entries = vec![];
+
+        while !dirs.is_empty() {
+            let (dir, ignores, ancestors) = dirs.remove(0);
+            let walk_error = |source| Error::Walk {
+                path: dir.clone(),
+                source,
+            };
+            let mut dir_iter = tokio::fs::read_dir(&dir).await.map_err(walk_error)?;
+            let mut children = vec![];
+            while let Some(entry) = dir_iter.next_entry().await.map_err(walk_error)? {
+                children.push(entry);
+            }
+            if self.sorted {
+                children.sort_by_key(|entry| entry.file_name());
+            }
+
+            for entry in children {
+                let path = entry.path();
+                let file_type = entry.file_type().await.map_err(walk_error)?;
+                let mut link = None;
+                let is_dir = if !file_type.is_symlink() {
+                    file_type.is_dir()
+                } else {
+                    match self.symlinks {
+                        Symlinks::Skip => continue,
+                        Symlinks::Store => {
+                            link = Some(tokio::fs::read_link(&path).await.map_err(walk_error)?);
+                            false
+                        }
+                        // a broken link is a file that cannot be read
+                        Symlinks::Follow => path.is_dir(),
+                    }
+                };
+                if self.excludes.is_excluded(&path, is_dir) {
+                    continue;
+                }
+                if let Some(ignores) = &ignores {
+                    if (is_dir && entry.file_name() == ".git") || ignores.is_ignored(&path, is_dir)
+                    {
+                        continue;
+                    }
+                }
+
+                let mut canonical = None;
+                if is_dir {
+                    let target = if file_type.is_symlink() {
+                        tokio::fs::canonicalize(&path).await.map_err(walk_error)?
+                    } else {
+                        ancestors[ancestors.len() - 1].join(entry.file_name())
+                    };
+                    if ancestors.contains(&target) {
+                        eprintln!("skip symlink cycle {path:?}");
+                        continue;
+                    }
+                    canonical = Some(target);
+                }
+
+                entries.push(Entry {
+                    name: entry_name(name_root, &path, is_dir, self.names)?,
+                    path: path.clone(),
+                    link,
+                });
+                if let Some(canonical) = canonical {
+                    let ignores = ignores.as_ref().map(|ignores| ignores.child(&path));
+                    let mut ancestors = ancestors.clone();
+                    ancestors.push(canonical);
+                    dirs.push((path, ignores, ancestors));
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Recursively lists the directories and files under `root`.
+#[cfg(test)]
+pub async fn walk_directory(root: impl AsRef<Path>) -> Result<Vec<Entry>> {
+    Walker::default().walk(root).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn entry_name_should_be_relative() {
+        let root = Path::new("/data/project");
+        assert_eq!(
+            entry_name(
+                root,
+                Path::new("/data/project/src/main.rs"),
+                false,
+                NonUtf8Names::Error
+            )
+            .unwrap(),
+            "src/main.rs"
+        );
+        assert_eq!(
+            entry_name(
+                root,
+                Path::new("/data/project/src"),
+                true,
+                NonUtf8Names::Error
+            )
+            .unwrap(),
+            "src/"
+        );
+
+        #[cfg(unix)]
+        {
+            use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+            let path = root.join(OsStr::from_bytes(b"caf\xe9.txt"));
+            assert!(matches!(
+                entry_name(root, &path, false, NonUtf8Names::Error),
+                Err(Error::Encoding(_))
+            ));
+            assert_eq!(
+                entry_name(root, &path, false, NonUtf8Names::Lossy).unwrap(),
+                "caf\u{fffd}.txt"
+            );
+            let path = root.join(OsStr::from_bytes(b"caf\xe9/\xc3\xa9t\xe9\xff"));
+            assert_eq!(
+                entry_name(root, &path, true, NonUtf8Names::Escape).unwrap(),
+                "caf%E9/\u{e9}t%E9%FF/"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn walk_directory_should_recurse() -> Result<()> {
+        let entries = walk_directory("src").await?;
+
+        let dir = entries.iter().position(|e| e.name == "async_zip/").unwrap();
+        let file = entries
+            .iter()
+            .position(|e| e.name == "async_zip/zip.rs")
+            .unwrap();
+        assert!(entries[dir].is_dir());
+        assert!(dir < file);
+        assert!(entries.iter().any(|e| e.name == "main.rs"));
+
+        let sorted = Walker::default().sorted(true).walk("src").await?;
+        let top = sorted
+            .iter()
+            .map(|e| e.name.trim_end_matches('/'))
+            .filter(|name| !name.contains('/'))
+            .collect::<Vec<_>>();
+        assert!(top.windows(2).all(|names| names[0] < names[1]));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn walk_relative_should_name_from_top() -> Result<()> {
+        let entries = Walker::default()
+            .walk_relative(Path::new("."), Path::new("./src/async_zip"))
+            .await?;
+
+        assert_eq!(entries[0].name, "src/async_zip/");
+        assert!(entries.iter().any(|e| e.name == "src/async_zip/zip.rs"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn walk_should_skip_excluded() -> anyhow::Result<()> {
+        let excludes = Excludes::new("src", &["async_zip/".to_owned(), "main.rs".to_owned()])?;
+        let entries = Walker::new(excludes).walk("src").await?;
+
+        assert!(entries.iter().any(|e| e.name == "option.rs"));
+        assert!(!entries.iter().any(|e| e.name == "main.rs"));
+        assert!(!entries.iter().any(|e| e.name.starts_with("async_zip")));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn walk_should_honor_ignore_files() -> anyhow::Result<()> {
+        let top = TempDir::new("ignore");
+        let root = top.join("project");
+        for dir in [".git", "build", "sub"] {
+            tokio::fs::create_dir_all(root.join(dir)).await?;
+        }
+        let files = [
+            ("../.gitignore", "*.tmp\n"),
+            (".gitignore", "*.log\nbuild/\n"),
+            ("sub/.zipignore", "!keep.log\nsecret.txt\n"),
+            (".git/HEAD", ""),
+            ("a.log", ""),
+            ("a.tmp", ""),
+            ("build/out", ""),
+            ("sub/keep.log", ""),
+            ("sub/other.log", ""),
+            ("sub/secret.txt", ""),
+            ("sub/ok.txt", ""),
+        ];
+        for (name, content) in files {
+            tokio::fs::write(root.join(name), content).await?;
+        }
+
+        let entries = Walker::default().ignore_files(&top).walk(&root).await?;
+        let mut names = entries.into_iter().map(|e| e.name).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                ".gitignore",
+                "sub/",
+                "sub/.zipignore",
+                "sub/keep.log",
+                "sub/ok.txt"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn walk_should_apply_symlink_policy() -> anyhow::Result<()> {
+        let root = TempDir::new("symlinks");
+        tokio::fs::create_dir_all(root.join("a")).await?;
+        tokio::fs::write(root.join("a/file"), "").await?;
+        tokio::fs::symlink("..", root.join("a/loop")).await?;
+        tokio::fs::symlink("a", root.join("link")).await?;
+        tokio::fs::symlink("a/file", root.join("flink")).await?;
+
+        let names = |symlinks| {
+            let root = root.to_path_buf();
+            async move {
+                let entries = Walker::default().symlinks(symlinks).walk(&root).await?;
+                let mut names = entries
+                    .into_iter()
+                    .map(|e| (e.name, e.link))
+                    .collect::<Vec<_>>();
+                names.sort();
+                anyhow::Ok(names)
+            }
+        };
+        let entry = |name: &str, link: Option<&str>| (name.to_owned(), link.map(PathBuf::from));
+
+        assert_eq!(
+            names(Symlinks::Follow).await?,
+            [
+                entry("a/", None),
+                entry("a/file", None),
+                entry("flink", None),
+                entry("link/", None),
+                entry("link/file", None),
+            ]
+        );
+        assert_eq!(
+            names(Symlinks::Store).await?,
+            [
+                entry("a/", None),
+                entry("a/file", None),
+                entry("a/loop", Some("..")),
+                entry("flink", Some("a/file")),
+                entry("link", Some("a")),
+            ]
+        );
+        assert_eq!(
+            names(Symlinks::Skip).await?,
+            [entry("a/", None), entry("a/file", None)]
+        );
+
+        Ok(())
+    }
+}
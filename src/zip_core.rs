@@ -1,210 +1,1617 @@
-use anyhow::{anyhow, bail, Result};
-use futures::{Future, Stream, StreamExt};
+use async_walkdir::{DirEntry, Filtering, WalkDir};
+use chrono::{DateTime, Datelike, Local, Timelike, Utc};
+use flate2::write::GzEncoder;
+use futures::{Stream, StreamExt};
+use std::collections::HashMap;
 use std::fs::File as StdFile;
-use std::io;
+use std::future::Future;
+use std::io::{self, Read, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use tokio::fs::{read_dir, DirEntry};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tar::EntryType;
 use tokio::io::AsyncWriteExt;
 use tokio::{
     fs::File,
-    io::AsyncReadExt,
-    sync::mpsc::{channel, Receiver, Sender},
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Semaphore,
+    },
+    task::JoinHandle,
 };
-use tokio_stream::wrappers::ReadDirStream;
-use zip::ZipWriter;
-use zip_extensions::write::ZipWriterExtensions;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
 
-use crate::{async_zip, is_exclude};
+use crate::{
+    async_zip::{self, SplitChunk, SINGLE_VOLUME_SIGNATURE},
+    error::{Error, Result},
+    exclude::Excludes,
+    output::Output,
+    walk::{Entry, Walker},
+};
 use ::async_zip as az;
 
-pub trait ZipCore {
-    async fn zip_entry(&self, path: impl AsRef<Path>) -> Result<()>;
+pub trait ZipCore: Send + Sync {
+    fn walker(&self) -> &Walker;
+
+    /// Extension of the written archives.
+    fn extension(&self) -> &str {
+        "zip"
+    }
+
+    /// Writes `entries` to the archive at `output`. The future is `Send`,
+    /// each directory is zipped in a task of its own.
+    fn write_archive<'a>(
+        &'a self,
+        entries: Vec<Entry>,
+        output: &'a Path,
+    ) -> impl Future<Output = Result<()>> + Send + 'a;
+
+    /// Zips the directory `path` into the archive `output`.
+    fn zip_entry<'a>(
+        &'a self,
+        path: impl AsRef<Path> + Send + Sync + 'a,
+        output: &'a Path,
+    ) -> impl Future<Output = Result<()>> + Send + 'a {
+        async move {
+            let entries = self.walker().walk(path).await?;
+            self.write_archive(entries, output).await
+        }
+    }
+
+    /// Zips `dirs` into the single archive `output`, each directory is a
+    /// folder named by its path relative to `root`. Directories inside
+    /// another one of `dirs` are zipped once, with it.
+    async fn zip_entries(&self, root: &Path, dirs: &[PathBuf], output: &Path) -> Result<()> {
+        let mut walked: Vec<&PathBuf> = vec![];
+        let mut entries = vec![];
+        for dir in dirs {
+            if walked.iter().any(|outer| dir.starts_with(outer)) {
+                continue;
+            }
+            entries.extend(self.walker().walk_relative(root, dir).await?);
+            walked.push(dir);
+        }
+        self.write_archive(entries, output).await
+    }
 }
 
 pub trait ZipEngine: ZipCore {
     type ZipStream: Stream<Item = io::Result<DirEntry>> + Unpin;
 
-    fn skip(&self, dir: DirEntry) -> bool;
+    /// Input directory
+    fn root(&self) -> &Path;
+
+    /// Whether all directories go to a single archive of the input directory.
+    fn single(&self) -> bool;
 
+    /// Where archives are written and how they are named.
+    fn output(&self) -> &Output;
+
+    /// Directories to archive, hidden and excluded ones already skipped.
     async fn get_stream(&self) -> Self::ZipStream;
 
-    async fn do_zip(&self) -> Result<()> {
+    /// Number of directories zipped at the same time.
+    fn jobs(&self) -> usize;
+
+    /// Zips the selected directories, failing directories do not stop the
+    /// others and are reported in the summary.
+    async fn do_zip(self) -> Result<Summary>
+    where
+        Self: Sized + 'static,
+    {
         let mut stream = self.get_stream().await;
+        let mut dirs = vec![];
+        while let Some(entry) = stream.next().await {
+            let entry = entry.map_err(|source| Error::Walk {
+                path: self.root().to_owned(),
+                source,
+            })?;
+            dirs.push(entry.path());
+        }
+        dirs.sort();
 
-        while let Some(Ok(entry)) = stream.next().await {
-            let filename = entry.file_name().into_string().unwrap();
-            let directory = entry.path();
-            // skip hidden directory and excluded directory
-            if !self.skip(entry) {
-                println!("filename: {}, dir: {:?}", filename, directory);
-                self.zip_entry(directory).await?
-            } else {
-                println!("skip {}", filename)
+        let mut summary = Summary::default();
+        if self.single() {
+            let result = self.zip_single(&dirs).await;
+            summary.add(self.root().to_owned(), result);
+            return Ok(summary);
+        }
+
+        // archive paths are known before zipping, directories sharing one
+        // would overwrite each other's archive so none of them is zipped
+        let mut outputs = Vec::with_capacity(dirs.len());
+        for directory in &dirs {
+            outputs.push(
+                self.output()
+                    .archive_path(directory, self.extension())
+                    .await,
+            );
+        }
+        let mut counts = HashMap::<PathBuf, usize>::new();
+        for output in outputs.iter().flatten() {
+            *counts.entry(output.clone()).or_default() += 1;
+        }
+
+        // each directory is zipped in a task of its own, up to `jobs` at the
+        // same time
+        let engine = Arc::new(self);
+        let jobs = Arc::new(Semaphore::new(engine.jobs().max(1)));
+        let mut zips = Vec::with_capacity(dirs.len());
+        for (directory, output) in dirs.into_iter().zip(outputs) {
+            let zip = match output {
+                Ok(output) if counts[&output] > 1 => Err(Error::DuplicateArchive(output)),
+                Ok(output) => {
+                    let (engine, jobs) = (engine.clone(), jobs.clone());
+                    let path = directory.clone();
+                    Ok(tokio::spawn(async move {
+                        let _permit = jobs.acquire().await;
+                        engine.zip_entry(&path, &output).await.map(|()| output)
+                    }))
+                }
+                Err(e) => Err(e),
+            };
+            zips.push((directory, zip));
+        }
+
+        // results are reported in the order of the directories, a task that
+        // panicked fails its directory only
+        for (directory, zip) in zips {
+            let result = match zip {
+                Ok(task) => task.await.unwrap_or_else(|e| Err(e.into())),
+                Err(e) => Err(e),
+            };
+            summary.add(directory, result);
+        }
+
+        Ok(summary)
+    }
+
+    /// Zips `dirs` into the archive of the input directory.
+    async fn zip_single(&self, dirs: &[PathBuf]) -> Result<PathBuf> {
+        let output = self
+            .output()
+            .archive_path(self.root(), self.extension())
+            .await?;
+        self.zip_entries(self.root(), dirs, &output).await?;
+        Ok(output)
+    }
+}
+
+/// Outcome of [`ZipEngine::do_zip`].
+#[derive(Default)]
+pub struct Summary {
+    /// Zipped directories with their archive
+    pub succeeded: Vec<(PathBuf, PathBuf)>,
+    pub failed: Vec<(PathBuf, Error)>,
+}
+
+impl Summary {
+    fn add(&mut self, directory: PathBuf, result: Result<PathBuf>) {
+        match result {
+            Ok(output) => {
+                println!("zipped {directory:?} to {output:?}");
+                self.succeeded.push((directory, output));
+            }
+            Err(e) => {
+                println!("failed to zip {directory:?}: {e}");
+                self.failed.push((directory, e));
             }
         }
+    }
+}
 
-        Ok(())
+/// Selects the directories that become archives, by their depth below the
+/// input directory (its children are at depth 1).
+#[derive(Clone)]
+struct DirSelector {
+    root: PathBuf,
+    excludes: Excludes,
+    min_depth: usize,
+    max_depth: usize,
+    /// Canonical output directory, never zipped
+    output_dir: Option<PathBuf>,
+    /// Whether links to directories are zipped like directories
+    follow_links: bool,
+}
+
+impl DirSelector {
+    fn filter(&self, entry: &DirEntry) -> Filtering {
+        let directory = entry.path();
+        let depth = directory
+            .strip_prefix(&self.root)
+            .map(|p| p.components().count())
+            .unwrap_or_default();
+
+        if depth > self.max_depth {
+            return Filtering::IgnoreDir;
+        }
+        // skip hidden directory and excluded directory, with their content
+        if self.skip(&directory) {
+            if directory.is_dir() {
+                println!("skip {directory:?}");
+            }
+            return Filtering::IgnoreDir;
+        }
+        if depth < self.min_depth {
+            return Filtering::Ignore;
+        }
+        Filtering::Continue
+    }
+
+    fn skip(&self, directory: &Path) -> bool {
+        let hidden = directory
+            .file_name()
+            .map(|name| name.to_string_lossy().starts_with('.'))
+            .unwrap_or_default();
+
+        let output = self.output_dir.as_ref().map_or(false, |output_dir| {
+            directory
+                .canonicalize()
+                .map_or(false, |directory| &directory == output_dir)
+        });
+
+        let link = !self.follow_links && directory.is_symlink();
+
+        hidden
+            || output
+            || link
+            || directory.is_file()
+            || self.excludes.is_excluded(directory, true)
     }
 }
 
 pub struct DirsZipEngine<T: ZipCore> {
     inner: T,
-    path: PathBuf,
-    excluded: Vec<PathBuf>,
+    selector: DirSelector,
+    single: bool,
+    output: Output,
+    jobs: usize,
 }
 
 impl<T: ZipCore> DirsZipEngine<T> {
-    pub fn new(inner: T, path: impl AsRef<Path>, excluded: Vec<PathBuf>) -> Self {
+    pub fn new(inner: T, path: impl AsRef<Path>, excludes: Excludes) -> Self {
         Self {
+            selector: DirSelector {
+                follow_links: inner.walker().follows_symlinks(),
+                root: path.as_ref().to_path_buf(),
+                excludes,
+                min_depth: 1,
+                max_depth: 1,
+                output_dir: None,
+            },
             inner,
-            path: path.as_ref().to_path_buf(),
-            excluded,
+            single: false,
+            output: Output::default(),
+            jobs: 1,
         }
     }
+
+    /// Zips up to `jobs` directories at the same time, one by default.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Writes archives as `output` says, beside the zipped directories by
+    /// default. The output directory is expected to exist.
+    pub fn output(mut self, output: Output) -> Self {
+        self.selector.output_dir = output.dir().and_then(|dir| dir.canonicalize().ok());
+        self.output = output;
+        self
+    }
+
+    /// Puts all directories in a single archive of the input directory,
+    /// instead of one archive per directory.
+    pub fn single(mut self, single: bool) -> Self {
+        self.single = single;
+        self
+    }
+
+    /// Archives the directories from `min_depth` to `max_depth` levels below
+    /// the input directory, only its children by default.
+    pub fn depth(mut self, min_depth: usize, max_depth: usize) -> Self {
+        self.selector.min_depth = min_depth;
+        self.selector.max_depth = max_depth;
+        self
+    }
 }
 
 impl<T: ZipCore> ZipCore for DirsZipEngine<T> {
-    async fn zip_entry(&self, path: impl AsRef<Path>) -> Result<()> {
-        self.inner.zip_entry(path).await
+    fn walker(&self) -> &Walker {
+        self.inner.walker()
+    }
+
+    fn extension(&self) -> &str {
+        self.inner.extension()
+    }
+
+    async fn write_archive(&self, entries: Vec<Entry>, output: &Path) -> Result<()> {
+        self.inner.write_archive(entries, output).await
     }
 }
 
 impl<T: ZipCore> ZipEngine for DirsZipEngine<T> {
-    type ZipStream = ReadDirStream;
+    type ZipStream = WalkDir;
 
-    async fn get_stream(&self) -> Self::ZipStream {
-        ReadDirStream::new(read_dir(&self.path).await.unwrap())
+    fn root(&self) -> &Path {
+        &self.selector.root
     }
 
-    fn skip(&self, dir: DirEntry) -> bool {
-        let filename = dir.file_name().into_string().unwrap();
-        let directory = dir.path();
+    fn single(&self) -> bool {
+        self.single
+    }
 
-        filename.starts_with(".")
-            || directory.is_file()
-            || is_exclude(
-                Some(dir.path().as_ref()),
-                &self.excluded,
-                directory.as_path(),
-            )
+    fn output(&self) -> &Output {
+        &self.output
+    }
+
+    fn jobs(&self) -> usize {
+        self.jobs
+    }
+
+    async fn get_stream(&self) -> Self::ZipStream {
+        let selector = self.selector.clone();
+        WalkDir::new(&self.selector.root).filter(move |entry| {
+            let filtering = selector.filter(&entry);
+            async move { filtering }
+        })
     }
 }
 
-pub struct AsyncZip;
+/// Size of the file chunks read ahead of the AsyncZip archive writer.
+const CHUNK_SIZE: usize = 64 * 1024;
 
-impl AsyncZip {
-    async fn handle_directory(
-        &self,
-        path: impl AsRef<Path>,
-    ) -> Result<Receiver<(String, Vec<u8>)>> {
-        let entries = self.walk_directory(path).await?;
+/// Default bound of the file data read ahead of the AsyncZip archive writer.
+pub const DEFAULT_MEMORY_BUDGET: usize = 64 * 1024 * 1024;
 
-        let (tx, rx) = channel(1024);
+/// File data read ahead of the archive writer, every entry is a `Start`, its
+/// data and an `End`.
+enum Chunk {
+    /// Entry name, Unix mode, modification time and whether the entry is
+    /// stored
+    Start(String, Option<u32>, Option<SystemTime>, bool),
+    Data(Vec<u8>),
+    End,
+}
 
-        for entry_path_buf in entries {
-            let tx = tx.clone();
+/// Backend of the `async_zip` crate, files are streamed to the archive while
+/// a reader task reads ahead of the compression, up to a memory budget. The
+/// crate compresses the entries of an archive one after the other, archives
+/// are compressed in parallel by the jobs.
+pub struct AsyncZip {
+    walker: Walker,
+    compression: az::Compression,
+    policy: async_zip::CompressionPolicy,
+    memory_budget: usize,
+    fixed_time: Option<SystemTime>,
+    normalize_modes: bool,
+}
 
-            tokio::spawn(async move {
-                Self::write_entry(entry_path_buf, tx).await.unwrap();
-            });
+impl AsyncZip {
+    pub fn new(walker: Walker) -> Self {
+        Self {
+            walker,
+            compression: az::Compression::Deflate,
+            policy: async_zip::CompressionPolicy::default(),
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+            fixed_time: None,
+            normalize_modes: false,
         }
+    }
 
-        Ok(rx)
+    /// Compression of the files, the level is dropped: the crate reads it
+    /// only when writing whole entries, not streamed ones. Its LZMA is not
+    /// the ZIP one, so LZMA is refused.
+    pub fn compression(mut self, compression: async_zip::Compression) -> Result<Self> {
+        self.compression = match compression {
+            async_zip::Compression::Store => az::Compression::Stored,
+            async_zip::Compression::Deflate { .. } => az::Compression::Deflate,
+            async_zip::Compression::Zstd { .. } => az::Compression::Zstd,
+            async_zip::Compression::Bzip2 { .. } => az::Compression::Bz,
+            async_zip::Compression::Lzma { .. } => {
+                return Err(Error::UnsupportedCompression(compression))
+            }
+        };
+        Ok(self)
     }
 
-    async fn write_entry(
-        input_path: impl AsRef<Path>,
-        tx: Sender<(String, Vec<u8>)>,
-    ) -> Result<()> {
-        let mut input_file = File::open(input_path.as_ref()).await?;
-        let input_file_size = input_file.metadata().await?.len() as usize;
+    /// Stores the files that `policy` picks instead of compressing them.
+    pub fn policy(mut self, policy: async_zip::CompressionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Stamps every entry with `time` in UTC, for reproducible archives.
+    pub fn fixed_time(mut self, time: Option<SystemTime>) -> Self {
+        self.fixed_time = time;
+        self
+    }
 
-        // read file data to buffer
-        let mut buffer = Vec::with_capacity(input_file_size);
-        input_file.read_to_end(&mut buffer).await?;
+    /// Records normalized Unix modes instead of the file ones, for
+    /// reproducible archives.
+    pub fn normalize_modes(mut self, normalize: bool) -> Self {
+        self.normalize_modes = normalize;
+        self
+    }
 
-        let filename = input_path
-            .as_ref()
-            .file_name()
-            .ok_or(anyhow!("Directory file path not valid UTF-8."))?
-            .to_str()
-            .ok_or(anyhow!("Directory file path not valid UTF-8."))?;
+    /// Bounds the file data read ahead of the archive writer, in bytes.
+    pub fn memory_budget(mut self, memory_budget: usize) -> Self {
+        self.memory_budget = memory_budget;
+        self
+    }
+
+    /// Reads the entries in order, in chunks, in a task of its own.
+    fn read_entries(&self, entries: Vec<Entry>) -> (Receiver<Result<Chunk>>, JoinHandle<()>) {
+        let (tx, rx) = channel((self.memory_budget / CHUNK_SIZE).max(1));
+        let normalize_modes = self.normalize_modes;
+        let policy = self.policy.clone();
+
+        let reader = tokio::spawn(async move {
+            if let Err(e) = Self::read_chunks(entries, normalize_modes, &policy, &tx).await {
+                // the receiver is gone when the archive failed already
+                tx.send(Err(e)).await.ok();
+            }
+        });
 
-        if let Err(e) = tx.send((filename.to_string(), buffer)).await {
-            bail!("Failed to send, err {}", e);
+        (rx, reader)
+    }
+
+    async fn read_chunks(
+        entries: Vec<Entry>,
+        normalize_modes: bool,
+        policy: &async_zip::CompressionPolicy,
+        tx: &Sender<Result<Chunk>>,
+    ) -> Result<()> {
+        let send = |chunk| async {
+            tx.send(Ok(chunk))
+                .await
+                .map_err(|_| async_zip::error::Error::ReceiverGone)
         };
 
+        for entry in entries {
+            let read_error = |e| async_zip::error::Error::Read(entry.path.clone(), e);
+            let meta = if entry.link.is_some() {
+                tokio::fs::symlink_metadata(&entry.path).await
+            } else {
+                tokio::fs::metadata(&entry.path).await
+            };
+            let meta = meta.map_err(read_error)?;
+            let (mode, modified) = (
+                async_zip::unix_mode(&meta, normalize_modes),
+                meta.modified().ok(),
+            );
+
+            // links are written with their target as content, directories
+            // as empty entries
+            if let Some(target) = entry.link_target() {
+                send(Chunk::Start(entry.name.clone(), mode, modified, false)).await?;
+                send(Chunk::Data(target)).await?;
+            } else if entry.is_dir() {
+                send(Chunk::Start(entry.name.clone(), mode, modified, true)).await?;
+            } else {
+                let mut input_file = File::open(&entry.path).await.map_err(read_error)?;
+                // the first chunk is the sample picking the method
+                let mut first = true;
+                loop {
+                    let mut data = Vec::with_capacity(CHUNK_SIZE);
+                    let read = (&mut input_file)
+                        .take(CHUNK_SIZE as u64)
+                        .read_to_end(&mut data)
+                        .await
+                        .map_err(read_error)?;
+                    if first {
+                        let stored = policy.stores(&entry.path, &data);
+                        send(Chunk::Start(entry.name.clone(), mode, modified, stored)).await?;
+                        first = false;
+                    }
+                    if read == 0 {
+                        break;
+                    }
+                    send(Chunk::Data(data)).await?;
+                }
+            }
+
+            send(Chunk::End).await?;
+        }
+
         Ok(())
     }
+}
 
-    async fn walk_directory(&self, path: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
-        let mut dirs = vec![path.as_ref().to_owned()];
-        let mut files = vec![];
+impl ZipCore for AsyncZip {
+    fn walker(&self) -> &Walker {
+        &self.walker
+    }
 
-        while !dirs.is_empty() {
-            let mut dir_iter = tokio::fs::read_dir(dirs.remove(0)).await?;
+    async fn write_archive(&self, entries: Vec<Entry>, output: &Path) -> Result<()> {
+        let (rx, reader) = self.read_entries(entries);
+        self.write_chunks(rx, output).await?;
+        // a reader that panicked fails the archive
+        reader.await?;
+        Ok(())
+    }
+}
 
-            while let Some(entry) = dir_iter.next_entry().await? {
-                let entry_path_buf = entry.path();
+impl AsyncZip {
+    /// Writes the entries read by `read_entries` to the archive at `output`,
+    /// each one a `Start`, its data and an `End`.
+    async fn write_chunks(&self, mut rx: Receiver<Result<Chunk>>, output: &Path) -> Result<()> {
+        let write_error = |source| Error::Write {
+            path: output.to_owned(),
+            source,
+        };
+        let archive = File::create(output).await.map_err(write_error)?;
+
+        let mut writer = az::write::ZipFileWriter::new(archive);
 
-                if entry_path_buf.is_dir() {
-                    dirs.push(entry_path_buf);
-                } else {
-                    files.push(entry_path_buf);
+        loop {
+            let (name, mode, modified, stored) = match rx.recv().await.transpose()? {
+                Some(Chunk::Start(name, mode, modified, stored)) => (name, mode, modified, stored),
+                None => break,
+                Some(_) => return Err(Error::EntryStream("entry data before its start")),
+            };
+            let compression = if stored {
+                az::Compression::Stored
+            } else {
+                self.compression
+            };
+            let mut builder = az::ZipEntryBuilder::new(name, compression);
+            // a fixed time is written in UTC, file times in local time like
+            // the self_async_zip zip type does without `--utc`
+            let date = match (self.fixed_time, modified) {
+                (Some(time), _) => Some(DateTime::<Utc>::from(time)),
+                (None, Some(modified)) => Some(DateTime::from_utc(
+                    DateTime::<Local>::from(modified).naive_local(),
+                    Utc,
+                )),
+                (None, None) => None,
+            };
+            if let Some(date) = date {
+                builder = builder.last_modification_date(az::ZipDateTime::from_chrono(&date));
+            }
+            if let Some(mode) = mode {
+                builder = builder.unix_permissions(mode as u16);
+            }
+            let mut entry_writer = writer.write_entry_stream(builder).await?;
+            loop {
+                match rx.recv().await.transpose()? {
+                    Some(Chunk::Data(data)) => {
+                        entry_writer.write_all(&data).await.map_err(write_error)?
+                    }
+                    Some(Chunk::End) => break,
+                    Some(Chunk::Start(..)) => {
+                        return Err(Error::EntryStream("entry start before the previous end"))
+                    }
+                    None => return Err(Error::EntryStream("reader stopped in an entry")),
                 }
             }
+            entry_writer.close().await?;
         }
 
-        Ok(files)
+        let mut archive = writer.close().await?;
+        archive.flush().await.map_err(write_error)?;
+        Ok(())
     }
 }
 
-impl ZipCore for AsyncZip {
-    async fn zip_entry(&self, path: impl AsRef<Path>) -> Result<()> {
-        println!("output {:?}", path.as_ref().with_extension("zip"));
-        let archive = File::create(path.as_ref().with_extension("zip"))
-            .await
-            .unwrap();
+pub struct Zip {
+    walker: Walker,
+    fixed_time: Option<SystemTime>,
+    normalize_modes: bool,
+}
 
-        let mut writer = az::write::ZipFileWriter::new(archive);
+impl Zip {
+    pub fn new(walker: Walker) -> Self {
+        Self {
+            walker,
+            fixed_time: None,
+            normalize_modes: false,
+        }
+    }
 
-        let mut rx = self.handle_directory(path).await?;
+    /// Stamps every entry with `time` in UTC, for reproducible archives.
+    pub fn fixed_time(mut self, time: Option<SystemTime>) -> Self {
+        self.fixed_time = time;
+        self
+    }
+
+    /// Records normalized Unix modes instead of the file ones, for
+    /// reproducible archives.
+    pub fn normalize_modes(mut self, normalize: bool) -> Self {
+        self.normalize_modes = normalize;
+        self
+    }
 
-        while let Some(data) = rx.recv().await {
-            let builder = az::ZipEntryBuilder::new(data.0, az::Compression::Deflate);
-            writer.write_entry_whole(builder, &data.1).await.unwrap();
+    fn write_zip(
+        entries: Vec<Entry>,
+        output: &Path,
+        options: FileOptions,
+        normalize_modes: bool,
+    ) -> Result<()> {
+        let file = StdFile::create(output).map_err(|source| Error::Write {
+            path: output.to_owned(),
+            source,
+        })?;
+        let mut zip = ZipWriter::new(file);
+        for entry in entries {
+            let read_error = |e| async_zip::error::Error::Read(entry.path.clone(), e);
+            if entry.link.is_some() {
+                return Err(Error::UnsupportedSymlink(entry.path));
+            }
+            let meta = std::fs::metadata(&entry.path).map_err(read_error)?;
+            let options = match async_zip::unix_mode(&meta, normalize_modes) {
+                Some(mode) => options.unix_permissions(mode),
+                None => options,
+            };
+            if entry.is_dir() {
+                zip.add_directory(entry.name, options)?;
+            } else {
+                zip.start_file(entry.name, options)?;
+                let mut f = StdFile::open(&entry.path).map_err(read_error)?;
+                io::copy(&mut f, &mut zip)?;
+            }
         }
+        zip.finish()?;
         Ok(())
     }
 }
 
-pub struct Zip;
-
 impl ZipCore for Zip {
-    async fn zip_entry(&self, path: impl AsRef<Path>) -> Result<()> {
-        let path = path.as_ref().to_owned().clone();
+    fn walker(&self) -> &Walker {
+        &self.walker
+    }
+
+    async fn write_archive(&self, entries: Vec<Entry>, output: &Path) -> Result<()> {
+        let output = output.to_owned();
+        let mut options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        if let Some(time) = self.fixed_time {
+            let time = DateTime::<Utc>::from(time);
+            let time = zip::DateTime::from_date_and_time(
+                time.year() as u16,
+                time.month() as u8,
+                time.day() as u8,
+                time.hour() as u8,
+                time.minute() as u8,
+                time.second() as u8,
+            )
+            .unwrap_or_default();
+            options = options.last_modified_time(time);
+        }
+        let normalize_modes = self.normalize_modes;
         tokio::task::spawn_blocking(move || {
-            let file = StdFile::create(path.with_extension("zip")).unwrap();
-            let mut zip = ZipWriter::new(file);
-            zip.create_from_directory(&path.to_path_buf()).unwrap();
-        });
+            Self::write_zip(entries, &output, options, normalize_modes)
+        })
+        .await
+        .map_err(Error::from)??;
+        Ok(())
+    }
+}
+
+/// Compression of a whole tar archive, levels are 0 - 9.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TarCompression {
+    None,
+    Gzip { level: u32 },
+    Zstd { level: u32 },
+}
+
+/// Backend of tar archives, optionally compressed with gzip or zstd, keeping
+/// modes, modification times and links.
+pub struct Tar {
+    compression: TarCompression,
+    walker: Walker,
+    fixed_time: Option<SystemTime>,
+    normalize_modes: bool,
+}
+
+impl Tar {
+    pub fn new(compression: TarCompression, walker: Walker) -> Self {
+        Self {
+            compression,
+            walker,
+            fixed_time: None,
+            normalize_modes: false,
+        }
+    }
+
+    /// Stamps every entry with `time`, for reproducible archives.
+    pub fn fixed_time(mut self, time: Option<SystemTime>) -> Self {
+        self.fixed_time = time;
+        self
+    }
+
+    /// Records normalized Unix modes instead of the file ones, for
+    /// reproducible archives.
+    pub fn normalize_modes(mut self, normalize: bool) -> Self {
+        self.normalize_modes = normalize;
+        self
+    }
+
+    fn write_tar(
+        entries: Vec<Entry>,
+        output: &Path,
+        compression: TarCompression,
+        fixed_time: Option<SystemTime>,
+        normalize_modes: bool,
+    ) -> Result<()> {
+        let write_error = |source| Error::Write {
+            path: output.to_owned(),
+            source,
+        };
+        let file = StdFile::create(output).map_err(write_error)?;
+        match compression {
+            TarCompression::None => {
+                Self::append_entries(file, entries, output, fixed_time, normalize_modes)?;
+            }
+            TarCompression::Gzip { level } => {
+                let encoder = GzEncoder::new(file, flate2::Compression::new(level));
+                Self::append_entries(encoder, entries, output, fixed_time, normalize_modes)?
+                    .finish()
+                    .map_err(write_error)?;
+            }
+            TarCompression::Zstd { level } => {
+                let encoder = zstd::Encoder::new(file, level as i32).map_err(write_error)?;
+                Self::append_entries(encoder, entries, output, fixed_time, normalize_modes)?
+                    .finish()
+                    .map_err(write_error)?;
+            }
+        }
         Ok(())
     }
+
+    /// Writes `entries` as a tar archive to `writer`, returning it once the
+    /// archive is complete.
+    fn append_entries<W: Write>(
+        writer: W,
+        entries: Vec<Entry>,
+        output: &Path,
+        fixed_time: Option<SystemTime>,
+        normalize_modes: bool,
+    ) -> Result<W> {
+        let write_error = |source| Error::Write {
+            path: output.to_owned(),
+            source,
+        };
+        let mut tar = tar::Builder::new(writer);
+        for entry in entries {
+            let read_error = |e| async_zip::error::Error::Read(entry.path.clone(), e);
+            let meta = if entry.link.is_some() {
+                std::fs::symlink_metadata(&entry.path)
+            } else {
+                std::fs::metadata(&entry.path)
+            }
+            .map_err(read_error)?;
+
+            let mut header = tar::Header::new_gnu();
+            let mtime = fixed_time
+                .or_else(|| meta.modified().ok())
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |time| time.as_secs());
+            header.set_mtime(mtime);
+            let mode = match async_zip::unix_mode(&meta, normalize_modes) {
+                Some(mode) => mode & 0o7777,
+                None if entry.is_dir() => 0o755,
+                None => 0o644,
+            };
+            header.set_mode(mode);
+
+            if let Some(target) = &entry.link {
+                header.set_entry_type(EntryType::Symlink);
+                header.set_size(0);
+                tar.append_link(&mut header, &entry.name, target)
+                    .map_err(write_error)?;
+            } else if entry.is_dir() {
+                header.set_entry_type(EntryType::Directory);
+                header.set_size(0);
+                tar.append_data(&mut header, &entry.name, io::empty())
+                    .map_err(write_error)?;
+            } else {
+                // the size of the open file, the data is cut to it
+                let f = StdFile::open(&entry.path).map_err(read_error)?;
+                let size = f.metadata().map_err(read_error)?.len();
+                header.set_size(size);
+                let mut data = SizedFile::new(f, size);
+                if let Err(e) = tar.append_data(&mut header, &entry.name, &mut data) {
+                    return Err(match data.error.take() {
+                        Some(e) => read_error(e).into(),
+                        None => write_error(e),
+                    });
+                }
+            }
+        }
+        tar.into_inner().map_err(write_error)
+    }
 }
 
-pub struct Zipper;
+/// The first `size` bytes of a file, fails when the file ends before and
+/// keeps the read error apart from the write ones.
+struct SizedFile {
+    file: io::Take<StdFile>,
+    error: Option<io::Error>,
+}
+
+impl SizedFile {
+    fn new(file: StdFile, size: u64) -> Self {
+        SizedFile {
+            file: file.take(size),
+            error: None,
+        }
+    }
+}
+
+impl Read for SizedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = match self.file.read(buf) {
+            Ok(0) if !buf.is_empty() && self.file.limit() > 0 => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "file shrank while it was archived",
+            )),
+            result => result,
+        };
+        result.map_err(|e| {
+            let kind = e.kind();
+            self.error = Some(e);
+            io::Error::from(kind)
+        })
+    }
+}
+
+impl ZipCore for Tar {
+    fn walker(&self) -> &Walker {
+        &self.walker
+    }
+
+    fn extension(&self) -> &str {
+        match self.compression {
+            TarCompression::None => "tar",
+            TarCompression::Gzip { .. } => "tar.gz",
+            TarCompression::Zstd { .. } => "tar.zst",
+        }
+    }
+
+    async fn write_archive(&self, entries: Vec<Entry>, output: &Path) -> Result<()> {
+        let output = output.to_owned();
+        let (compression, fixed_time) = (self.compression, self.fixed_time);
+        let normalize_modes = self.normalize_modes;
+        tokio::task::spawn_blocking(move || {
+            Self::write_tar(entries, &output, compression, fixed_time, normalize_modes)
+        })
+        .await
+        .map_err(Error::from)??;
+        Ok(())
+    }
+}
+
+pub struct Zipper {
+    compression: async_zip::Compression,
+    policy: async_zip::CompressionPolicy,
+    walker: Walker,
+    fixed_time: Option<SystemTime>,
+    utc: bool,
+    normalize_modes: bool,
+    comment: Option<String>,
+    source_comments: bool,
+    password: Option<String>,
+    split_size: Option<u64>,
+}
+
+impl Zipper {
+    pub fn new(compression: async_zip::Compression, walker: Walker) -> Self {
+        Self {
+            compression,
+            policy: async_zip::CompressionPolicy::default(),
+            walker,
+            fixed_time: None,
+            utc: false,
+            normalize_modes: false,
+            comment: None,
+            source_comments: false,
+            password: None,
+            split_size: None,
+        }
+    }
+
+    /// Stamps every entry with `time` in UTC, for reproducible archives.
+    pub fn fixed_time(mut self, time: Option<SystemTime>) -> Self {
+        self.fixed_time = time;
+        self
+    }
+
+    /// Stores the files that `policy` picks instead of compressing them.
+    pub fn policy(mut self, policy: async_zip::CompressionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Writes the DOS modification times in UTC instead of local time.
+    pub fn utc(mut self, utc: bool) -> Self {
+        self.utc = utc;
+        self
+    }
+
+    /// Sets the comment of every archive.
+    pub fn comment(mut self, comment: Option<String>) -> Self {
+        self.comment = comment;
+        self
+    }
+
+    /// Comments each entry with its source path.
+    pub fn source_comments(mut self, source_comments: bool) -> Self {
+        self.source_comments = source_comments;
+        self
+    }
+
+    /// Encrypts the file entries with `password`.
+    pub fn password(mut self, password: Option<String>) -> Self {
+        self.password = password;
+        self
+    }
+
+    /// Splits each archive in volumes of at most `split_size` bytes.
+    pub fn split_size(mut self, split_size: Option<u64>) -> Self {
+        self.split_size = split_size;
+        self
+    }
+
+    /// Records normalized Unix modes instead of the file ones, for
+    /// reproducible archives.
+    pub fn normalize_modes(mut self, normalize: bool) -> Self {
+        self.normalize_modes = normalize;
+        self
+    }
+}
+
+impl Zipper {
+    /// Writes the volumes of a split archive, `output` is the last one and
+    /// the others are named .z01, .z02, ...
+    async fn write_volumes(z: async_zip::Zipper, output: &Path) -> Result<()> {
+        let volume_path = |volume: u32| output.with_extension(format!("z{volume:02}"));
+        let write_error = |path: &Path, source| Error::Write {
+            path: path.to_owned(),
+            source,
+        };
+
+        let mut chunks = z.split_stream();
+        let mut volume = 1;
+        let mut path = volume_path(volume);
+        let mut f = File::create(&path)
+            .await
+            .map_err(|e| write_error(&path, e))?;
+        while let Some(chunk) = chunks.next().await {
+            match chunk.map_err(Error::from_stream)? {
+                SplitChunk::Data(data) => f
+                    .write_all(&data)
+                    .await
+                    .map_err(|e| write_error(&path, e))?,
+                SplitChunk::NextVolume => {
+                    f.flush().await.map_err(|e| write_error(&path, e))?;
+                    volume += 1;
+                    path = volume_path(volume);
+                    f = File::create(&path)
+                        .await
+                        .map_err(|e| write_error(&path, e))?;
+                }
+            }
+        }
+        // an archive that fits one volume is marked as such
+        if volume == 1 {
+            f.seek(SeekFrom::Start(0))
+                .await
+                .map_err(|e| write_error(&path, e))?;
+            f.write_all(&SINGLE_VOLUME_SIGNATURE.to_le_bytes())
+                .await
+                .map_err(|e| write_error(&path, e))?;
+        }
+        f.flush().await.map_err(|e| write_error(&path, e))?;
+        drop(f);
+
+        tokio::fs::rename(&path, output)
+            .await
+            .map_err(|e| write_error(output, e))?;
+
+        // volumes past the last one are left by a previous, longer archive
+        for volume in volume + 1.. {
+            let path = volume_path(volume);
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+                Err(e) => return Err(write_error(&path, e)),
+            }
+        }
+        Ok(())
+    }
+}
 
 impl ZipCore for Zipper {
-    async fn zip_entry(&self, path: impl AsRef<Path>) -> Result<()> {
-        let z = async_zip::Zipper::from_directory(path.as_ref()).await?;
+    fn walker(&self) -> &Walker {
+        &self.walker
+    }
+
+    async fn write_archive(&self, entries: Vec<Entry>, output: &Path) -> Result<()> {
+        let mut z = async_zip::Zipper::from_entries(entries.into_iter())
+            .compression(self.compression)
+            .policy(self.policy.clone())
+            .fixed_time(self.fixed_time)
+            .utc(self.utc)
+            .normalize_modes(self.normalize_modes)
+            .comment(self.comment.clone().unwrap_or_default())
+            .password(self.password.clone())
+            .split_size(self.split_size);
+        if self.source_comments {
+            z = z.entry_comments(|path| Some(path.display().to_string()));
+        }
+        if self.split_size.is_some() {
+            return Self::write_volumes(z, output).await;
+        }
         let mut chunks = z.zipped_stream();
 
-        println!("output {:?}", path.as_ref().with_extension("zip"));
-        let mut f = File::create(path.as_ref().with_extension("zip")).await?;
+        let write_error = |source| Error::Write {
+            path: output.to_owned(),
+            source,
+        };
+        let mut f = File::create(output).await.map_err(write_error)?;
 
         while let Some(chunk) = chunks.next().await {
-            f.write_all(&chunk.expect("invalid zip read")).await?
+            let chunk = chunk.map_err(Error::from_stream)?;
+            f.write_all(&chunk).await.map_err(write_error)?;
+        }
+        f.flush().await.map_err(write_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File as StdFile, path::PathBuf};
+
+    use chrono::{DateTime, Datelike, Local, Timelike};
+    use futures::StreamExt;
+
+    use tokio::sync::mpsc::channel;
+
+    use super::{
+        AsyncZip, Chunk, DirsZipEngine, SizedFile, Tar, TarCompression, Zip, ZipCore, ZipEngine,
+        Zipper,
+    };
+    use crate::{
+        async_zip::{Compression, CompressionPolicy, ZipReader},
+        error::Error,
+        exclude::Excludes,
+        output::Output,
+        test_util::{noise, TempDir},
+        walk::{Entry, Walker},
+    };
+
+    #[tokio::test]
+    async fn get_stream_should_select_depth() -> anyhow::Result<()> {
+        let root = TempDir::new("depth");
+        for dir in ["a/b/c", "a/d", ".hidden/e", "f/g", "h/node_modules"] {
+            tokio::fs::create_dir_all(root.join(dir)).await?;
+        }
+
+        let zipper = Zipper::new(Compression::Store, Walker::default());
+        let excludes = Excludes::new(&root, &["node_modules".to_owned()])?;
+        let engine = DirsZipEngine::new(zipper, &root, excludes).depth(2, 2);
+        let mut dirs = engine
+            .get_stream()
+            .await
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>()
+            .await;
+        dirs.sort();
+
+        let expected = ["a/b", "a/d", "f/g"]
+            .iter()
+            .map(|dir| root.join(dir))
+            .collect::<Vec<PathBuf>>();
+        assert_eq!(dirs, expected);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn do_zip_should_write_single_archive() -> anyhow::Result<()> {
+        let top = TempDir::new("single");
+        let root = top.join("input");
+        for (name, content) in [("a/x.txt", "x"), ("b/c/y.txt", "y")] {
+            let path = root.join(name);
+            tokio::fs::create_dir_all(path.parent().unwrap()).await?;
+            tokio::fs::write(path, content).await?;
+        }
+
+        // b/c is selected and zipped with b
+        let zipper = Zipper::new(Compression::Store, Walker::default());
+        DirsZipEngine::new(zipper, &root, Excludes::empty())
+            .single(true)
+            .depth(1, 2)
+            .do_zip()
+            .await?;
+
+        let reader = ZipReader::open(top.join("input.zip")).await?;
+        let mut names = reader
+            .entries()
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["a/", "a/x.txt", "b/", "b/c/", "b/c/y.txt"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn do_zip_should_name_archives_in_output_dir() -> anyhow::Result<()> {
+        let root = TempDir::new("output");
+        let output_dir = root.join("archives");
+        for dir in ["v1.2", "v1.3"] {
+            tokio::fs::create_dir_all(root.join(dir)).await?;
+        }
+        tokio::fs::create_dir_all(&output_dir).await?;
+
+        let zipper = Zipper::new(Compression::Store, Walker::default());
+        let output = Output::new(Some(output_dir.clone()), "{name}.{ext}")?;
+        let summary = DirsZipEngine::new(zipper, &root, Excludes::empty())
+            .output(output)
+            .jobs(2)
+            .do_zip()
+            .await?;
+        assert_eq!(summary.succeeded.len(), 2);
+        assert!(summary.failed.is_empty());
+
+        let mut archives = vec![];
+        let mut dir = tokio::fs::read_dir(&output_dir).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            archives.push(entry.file_name().into_string().unwrap());
         }
+        archives.sort();
+        assert_eq!(archives, ["v1.2.zip", "v1.3.zip"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn do_zip_should_fail_directories_sharing_an_archive() -> anyhow::Result<()> {
+        let root = TempDir::new("shared_archive");
+        let output_dir = TempDir::new("shared_archive_output");
+        for dir in ["a/src", "b/src", "c/lib"] {
+            tokio::fs::create_dir_all(root.join(dir)).await?;
+        }
+
+        let zipper = Zipper::new(Compression::Store, Walker::default());
+        let output = Output::new(Some(output_dir.to_path_buf()), "{name}.{ext}")?;
+        let summary = DirsZipEngine::new(zipper, &root, Excludes::empty())
+            .output(output)
+            .depth(2, 2)
+            .do_zip()
+            .await?;
+        assert_eq!(
+            summary.succeeded,
+            [(root.join("c/lib"), output_dir.join("lib.zip"))]
+        );
+        let failed = summary
+            .failed
+            .iter()
+            .map(|(dir, e)| (dir.clone(), matches!(e, Error::DuplicateArchive(_))))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            failed,
+            [(root.join("a/src"), true), (root.join("b/src"), true)]
+        );
+        assert!(!output_dir.join("src.zip").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn do_zip_should_report_failures() -> anyhow::Result<()> {
+        let root = TempDir::new("failures");
+        for dir in ["a", "b"] {
+            tokio::fs::create_dir_all(root.join(dir)).await?;
+        }
+
+        // the output directory does not exist
+        let zipper = Zipper::new(Compression::Store, Walker::default());
+        let output = Output::new(Some(root.join("missing")), "{name}.{ext}")?;
+        let summary = DirsZipEngine::new(zipper, &root, Excludes::empty())
+            .output(output)
+            .jobs(4)
+            .do_zip()
+            .await?;
+        assert!(summary.succeeded.is_empty());
+        let mut failed = summary
+            .failed
+            .iter()
+            .map(|(dir, _)| dir.clone())
+            .collect::<Vec<_>>();
+        failed.sort();
+        assert_eq!(failed, [root.join("a"), root.join("b")]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn zip_should_wait_for_archive() -> anyhow::Result<()> {
+        let root = TempDir::new("zip");
+        let zip = Zip::new(Walker::default());
+
+        let output = root.join("src.zip");
+        zip.zip_entry("src", &output).await?;
+        let reader = ZipReader::open(&output).await?;
+        assert!(reader.entries().iter().any(|e| e.name == "main.rs"));
+
+        let missing = vec![Entry {
+            path: root.join("missing"),
+            name: "missing".to_owned(),
+            link: None,
+        }];
+        assert!(zip
+            .write_archive(missing, &root.join("missing.zip"))
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn do_zip_should_fail_only_directories_whose_task_panicked() -> anyhow::Result<()> {
+        struct Panicking(Walker);
+
+        impl ZipCore for Panicking {
+            fn walker(&self) -> &Walker {
+                &self.0
+            }
+
+            async fn write_archive(
+                &self,
+                _: Vec<Entry>,
+                output: &std::path::Path,
+            ) -> crate::error::Result<()> {
+                assert!(!output.ends_with("a.zip"), "cannot zip {output:?}");
+                tokio::fs::write(output, "").await?;
+                Ok(())
+            }
+        }
+
+        let root = TempDir::new("panics");
+        for dir in ["a", "b"] {
+            tokio::fs::create_dir_all(root.join(dir)).await?;
+        }
+
+        let summary = DirsZipEngine::new(Panicking(Walker::default()), &root, Excludes::empty())
+            .jobs(2)
+            .do_zip()
+            .await?;
+        assert_eq!(summary.succeeded.len(), 1);
+        assert_eq!(summary.succeeded[0].0, root.join("b"));
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(summary.failed[0].0, root.join("a"));
+        assert!(matches!(summary.failed[0].1, Error::Join(_)));
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn do_zip_should_fail_only_directories_with_bad_names() -> anyhow::Result<()> {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        use crate::error::Error;
+
+        let root = TempDir::new("bad_names");
+        for dir in ["a", "b"] {
+            tokio::fs::create_dir_all(root.join(dir)).await?;
+        }
+        tokio::fs::write(root.join("a").join(OsStr::from_bytes(b"caf\xe9")), "").await?;
+        tokio::fs::write(root.join("b/ok.txt"), "").await?;
+
+        let zipper = Zipper::new(Compression::Store, Walker::default());
+        let summary = DirsZipEngine::new(zipper, &root, Excludes::empty())
+            .do_zip()
+            .await?;
+        assert_eq!(summary.succeeded.len(), 1);
+        assert_eq!(summary.succeeded[0].0, root.join("b"));
+        assert_eq!(summary.failed.len(), 1);
+        assert!(matches!(summary.failed[0].1, Error::Encoding(_)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_zip_should_stream_entries() -> anyhow::Result<()> {
+        let root = TempDir::new("async_zip");
+        let extracted = root.join("src");
+
+        // a budget below one chunk still reads ahead one chunk
+        let output = root.join("src.zip");
+        AsyncZip::new(Walker::default())
+            .memory_budget(1)
+            .zip_entry("src", &output)
+            .await?;
+
+        crate::extract::extract(&output, &extracted, None).await?;
+        assert_eq!(
+            tokio::fs::read(extracted.join("async_zip/zip.rs")).await?,
+            tokio::fs::read("src/async_zip/zip.rs").await?
+        );
+
+        // entries keep the local modification time of their file
+        let modified = DateTime::<Local>::from(std::fs::metadata("src/main.rs")?.modified()?);
+        let mut zip = zip::ZipArchive::new(StdFile::open(&output)?)?;
+        let date = zip.by_name("main.rs")?.last_modified();
+        assert_eq!(
+            (
+                date.year(),
+                date.month(),
+                date.day(),
+                date.hour(),
+                date.minute()
+            ),
+            (
+                modified.year() as u16,
+                modified.month() as u8,
+                modified.day() as u8,
+                modified.hour() as u8,
+                modified.minute() as u8
+            )
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_zip_should_fail_on_broken_entry_streams() -> anyhow::Result<()> {
+        let root = TempDir::new("broken");
+        let output = root.join("broken.zip");
+        let async_zip = AsyncZip::new(Walker::default());
+
+        let start = || Chunk::Start("a.txt".to_owned(), None, None, false);
+        let data = || Chunk::Data(b"a".to_vec());
+        // the reader stops in an entry, or sends its chunks out of order
+        for chunks in [
+            vec![start(), data()],
+            vec![start(), data(), start()],
+            vec![data()],
+        ] {
+            let (tx, rx) = channel(chunks.len());
+            for chunk in chunks {
+                assert!(tx.send(Ok(chunk)).await.is_ok());
+            }
+            drop(tx);
+            let result = async_zip.write_chunks(rx, &output).await;
+            assert!(matches!(result, Err(Error::EntryStream(_))));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_zip_should_store_policy_entries() -> anyhow::Result<()> {
+        let root = TempDir::new("async_policy");
+        let input = root.join("input");
+        tokio::fs::create_dir_all(&input).await?;
+        let text = "2023-02-01 INFO request served\n".repeat(1000);
+        tokio::fs::write(input.join("server.log"), &text).await?;
+        tokio::fs::write(input.join("server.log.gz"), &text).await?;
+
+        let output = root.join("input.zip");
+        AsyncZip::new(Walker::default())
+            .policy(CompressionPolicy::default().store_extensions(["gz"]))
+            .zip_entry(&input, &output)
+            .await?;
+
+        let reader = ZipReader::open(&output).await?;
+        let method = |name| {
+            let entry = reader.entries().iter().find(|e| e.name == name);
+            entry.expect("no entry").compression
+        };
+        assert_eq!(method("server.log"), 8);
+        assert_eq!(method("server.log.gz"), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn zipper_should_split_volumes() -> anyhow::Result<()> {
+        use crate::async_zip::SINGLE_VOLUME_SIGNATURE;
+
+        let root = TempDir::new("split");
+        let input = root.join("input");
+        tokio::fs::create_dir_all(&input).await?;
+        // xorshift noise does not compress
+        let noise = noise(200 * 1024);
+        for name in ["a.bin", "b.bin"] {
+            tokio::fs::write(input.join(name), &noise).await?;
+        }
+
+        let split_size = 64 * 1024;
+        let output = root.join("input.zip");
+        Zipper::new(Compression::Store, Walker::default())
+            .split_size(Some(split_size))
+            .zip_entry(&input, &output)
+            .await?;
+
+        let mut volumes = vec![];
+        for volume in 1..=6 {
+            volumes.push(output.with_extension(format!("z{volume:02}")));
+        }
+        volumes.push(output.clone());
+        for volume in &volumes {
+            assert!(tokio::fs::metadata(volume).await?.len() <= split_size);
+        }
+        assert!(!output.with_extension("z07").exists());
+
+        let mut reader = ZipReader::open(&output).await?;
+        let entries = reader.entries().to_vec();
+        assert_eq!(entries.len(), 2);
+        for entry in &entries {
+            let mut data = vec![];
+            reader.read_entry(entry, &mut data).await?;
+            assert_eq!(data, noise);
+        }
+
+        // an archive that fits one volume is marked as such, the volumes
+        // of the previous archive are removed
+        let small = root.join("small");
+        tokio::fs::create_dir_all(&small).await?;
+        tokio::fs::write(small.join("a.txt"), "a").await?;
+        Zipper::new(Compression::Store, Walker::default())
+            .split_size(Some(split_size))
+            .zip_entry(&small, &output)
+            .await?;
+        for volume in &volumes[..6] {
+            assert!(!volume.exists());
+        }
+        let archive = tokio::fs::read(&output).await?;
+        assert_eq!(archive[..4], SINGLE_VOLUME_SIGNATURE.to_le_bytes());
+        let reader = ZipReader::open(&output).await?;
+        assert_eq!(reader.entries()[0].name, "a.txt");
+
+        Ok(())
+    }
+
+    #[test]
+    fn sized_file_should_fail_when_the_file_shrank() -> anyhow::Result<()> {
+        use std::{
+            fs::File,
+            io::{ErrorKind, Read},
+        };
+
+        let root = TempDir::new("sized_file");
+        let path = root.join("data");
+        std::fs::write(&path, "0123456789")?;
+
+        // a file that grew is cut to its size in the header
+        let mut data = vec![];
+        SizedFile::new(File::open(&path)?, 4).read_to_end(&mut data)?;
+        assert_eq!(data, b"0123");
+
+        let mut shrank = SizedFile::new(File::open(&path)?, 20);
+        assert!(shrank.read_to_end(&mut vec![]).is_err());
+        let error = shrank.error.map(|e| e.kind());
+        assert_eq!(error, Some(ErrorKind::UnexpectedEof));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn tar_should_keep_modes_times_and_links() -> anyhow::Result<()> {
+        use std::{
+            io::Read,
+            os::unix::fs::PermissionsExt,
+            time::{Duration, UNIX_EPOCH},
+        };
+
+        use tar::EntryType;
+
+        use crate::walk::Symlinks;
+
+        let root = TempDir::new("tar");
+        let input = root.join("input");
+        tokio::fs::create_dir_all(input.join("bin")).await?;
+        tokio::fs::write(input.join("bin/run.sh"), "#!/bin/sh\n").await?;
+        let permissions = std::fs::Permissions::from_mode(0o750);
+        tokio::fs::set_permissions(input.join("bin/run.sh"), permissions).await?;
+        tokio::fs::symlink("bin/run.sh", input.join("run")).await?;
+
+        let time = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        for compression in [
+            TarCompression::None,
+            TarCompression::Gzip { level: 6 },
+            TarCompression::Zstd { level: 3 },
+        ] {
+            let walker = Walker::default().symlinks(Symlinks::Store);
+            let tar = Tar::new(compression, walker).fixed_time(Some(time));
+            let output = root.join(format!("input.{}", tar.extension()));
+            tar.zip_entry(&input, &output).await?;
+
+            let file = std::fs::File::open(&output)?;
+            let reader: Box<dyn Read> = match compression {
+                TarCompression::None => Box::new(file),
+                TarCompression::Gzip { .. } => Box::new(flate2::read::GzDecoder::new(file)),
+                TarCompression::Zstd { .. } => Box::new(zstd::Decoder::new(file)?),
+            };
+            let mut archive = tar::Archive::new(reader);
+            let mut names = vec![];
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                let header = entry.header().clone();
+                assert_eq!(header.mtime()?, 1_600_000_000);
+                let name = entry.path()?.to_string_lossy().into_owned();
+                match name.trim_end_matches('/') {
+                    "bin" => assert_eq!(header.entry_type(), EntryType::Directory),
+                    "bin/run.sh" => {
+                        assert_eq!(header.mode()?, 0o750);
+                        let mut content = String::new();
+                        entry.read_to_string(&mut content)?;
+                        assert_eq!(content, "#!/bin/sh\n");
+                    }
+                    "run" => {
+                        assert_eq!(header.entry_type(), EntryType::Symlink);
+                        let target = header.link_name()?.expect("no link target");
+                        assert_eq!(target, PathBuf::from("bin/run.sh"));
+                    }
+                    other => panic!("unexpected entry {other}"),
+                }
+                names.push(name);
+            }
+            assert_eq!(names.len(), 3, "{names:?}");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reproducible_archives_should_be_identical() -> anyhow::Result<()> {
+        let root = TempDir::new("reproducible");
+        let files = [
+            ("a.txt", "a"),
+            ("b/c.txt", "c"),
+            ("b/d.txt", "d"),
+            ("e.txt", "e"),
+        ];
+        // same trees, created in a different order
+        for (tree, files) in [
+            ("one", files.to_vec()),
+            ("two", files.iter().rev().cloned().collect()),
+        ] {
+            for (name, content) in files {
+                let path = root.join(tree).join(name);
+                tokio::fs::create_dir_all(path.parent().unwrap()).await?;
+                tokio::fs::write(path, content).await?;
+            }
+        }
+
+        let time =
+            Some(std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000));
+        let walker = Walker::default().sorted(true);
+        let zip = Zip::new(walker.clone())
+            .fixed_time(time)
+            .normalize_modes(true);
+        let zipper = Zipper::new(Compression::Deflate { level: 6 }, walker.clone())
+            .fixed_time(time)
+            .normalize_modes(true);
+        let async_zip = AsyncZip::new(walker).fixed_time(time).normalize_modes(true);
+
+        let archive = |tree: &str, backend: &str| root.join(format!("{tree}-{backend}.zip"));
+        for tree in ["one", "two"] {
+            zip.zip_entry(root.join(tree), &archive(tree, "zip"))
+                .await?;
+            zipper
+                .zip_entry(root.join(tree), &archive(tree, "zipper"))
+                .await?;
+            async_zip
+                .zip_entry(root.join(tree), &archive(tree, "async_zip"))
+                .await?;
+        }
+        for backend in ["zip", "zipper", "async_zip"] {
+            assert_eq!(
+                tokio::fs::read(archive("one", backend)).await?,
+                tokio::fs::read(archive("two", backend)).await?,
+                "{backend}"
+            );
+        }
+
         Ok(())
     }
 }